@@ -31,6 +31,7 @@ pub const TYPE_SET: u8 = CLASS_UNIVERSAL | CONSTRUCTED | 17;
 /// - extended tag IDs.
 /// - indefinite lengths (disallowed by DER).
 /// - INTEGER values not representable by i64.
+/// - unsigned values not representable by u64.
 pub struct AsnReader<'a> {
     inner: &'a [u8],
 }
@@ -120,6 +121,33 @@ impl<'a> AsnReader<'a> {
         decode_i64(val)
     }
 
+    pub fn read_u64_type(&mut self, expected_ident: u8) -> Result<u64> {
+        let ident = self.read_byte()?;
+        if ident != expected_ident {
+            return Err(Error::AsnWrongType);
+        }
+        let val_len = self.read_length()?;
+        if val_len > self.inner.len() {
+            return Err(Error::AsnInvalidLen);
+        }
+        let (val, remaining) = self.inner.split_at(val_len);
+        self.inner = remaining;
+        decode_u64(val)
+    }
+
+    pub fn read_null_type(&mut self, expected_ident: u8) -> Result<()> {
+        let ident = self.read_byte()?;
+        if ident != expected_ident {
+            return Err(Error::AsnWrongType);
+        }
+        let null_len = self.read_length()?;
+        if null_len == 0 {
+            Ok(())
+        } else {
+            Err(Error::AsnInvalidLen)
+        }
+    }
+
     pub fn read_raw(&mut self, expected_ident: u8) -> Result<&'a [u8]> {
         let ident = self.read_byte()?;
         if ident != expected_ident {
@@ -182,16 +210,7 @@ impl<'a> AsnReader<'a> {
     }
 
     pub fn read_asn_null(&mut self) -> Result<()> {
-        let ident = self.read_byte()?;
-        if ident != TYPE_NULL {
-            return Err(Error::AsnWrongType);
-        }
-        let null_len = self.read_length()?;
-        if null_len == 0 {
-            Ok(())
-        } else {
-            Err(Error::AsnInvalidLen)
-        }
+        self.read_null_type(TYPE_NULL)
     }
 
     pub fn read_asn_objectidentifier(&mut self) -> Result<Oid<'a>> {
@@ -216,30 +235,38 @@ impl<'a> AsnReader<'a> {
         self.read_constructed(TYPE_SEQUENCE, f)
     }
 
-    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
     pub fn read_snmp_counter32(&mut self) -> Result<u32> {
-        self.read_i64_type(snmp::TYPE_COUNTER32).map(|v| v as u32)
+        Ok(u32::try_from(self.read_u64_type(snmp::TYPE_COUNTER32)?)?)
     }
 
-    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
     pub fn read_snmp_unsigned32(&mut self) -> Result<u32> {
-        self.read_i64_type(snmp::TYPE_UNSIGNED32).map(|v| v as u32)
+        Ok(u32::try_from(self.read_u64_type(snmp::TYPE_UNSIGNED32)?)?)
     }
 
-    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation)]
     pub fn read_snmp_timeticks(&mut self) -> Result<u32> {
-        self.read_i64_type(snmp::TYPE_TIMETICKS).map(|v| v as u32)
+        Ok(u32::try_from(self.read_u64_type(snmp::TYPE_TIMETICKS)?)?)
     }
 
-    #[allow(clippy::cast_sign_loss)]
     pub fn read_snmp_counter64(&mut self) -> Result<u64> {
-        self.read_i64_type(snmp::TYPE_COUNTER64).map(|v| v as u64)
+        self.read_u64_type(snmp::TYPE_COUNTER64)
     }
 
     pub fn read_snmp_opaque(&mut self) -> Result<&'a [u8]> {
         self.read_raw(snmp::TYPE_OPAQUE)
     }
 
+    pub fn read_snmp_nosuchobject(&mut self) -> Result<()> {
+        self.read_null_type(snmp::SNMP_NOSUCHOBJECT)
+    }
+
+    pub fn read_snmp_nosuchinstance(&mut self) -> Result<()> {
+        self.read_null_type(snmp::SNMP_NOSUCHINSTANCE)
+    }
+
+    pub fn read_snmp_endofmibview(&mut self) -> Result<()> {
+        self.read_null_type(snmp::SNMP_ENDOFMIBVIEW)
+    }
+
     pub fn read_snmp_ipaddress(&mut self) -> Result<[u8; 4]> {
         let val = self.read_raw(snmp::TYPE_IPADDRESS)?;
         if val.len() != 4 {
@@ -251,6 +278,11 @@ impl<'a> AsnReader<'a> {
     pub fn bytes_left(&self) -> usize {
         self.inner.len()
     }
+
+    /// Returns the bytes which have not been read yet.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.inner
+    }
 }
 
 fn decode_i64(i: &[u8]) -> Result<i64> {
@@ -268,3 +300,26 @@ fn decode_i64(i: &[u8]) -> Result<i64> {
     }
     Ok(ret)
 }
+
+/// Decodes an unsigned integer (Counter32, Gauge32, TimeTicks, Counter64).
+///
+/// BER requires a leading zero octet if the most significant bit is set, so
+/// the full u64 range may take up to 9 bytes. Values without that octet are
+/// still read as unsigned, as some agents omit it.
+fn decode_u64(i: &[u8]) -> Result<u64> {
+    if i.is_empty() {
+        return Err(Error::AsnInvalidLen);
+    }
+    let i = if i.len() > mem::size_of::<u64>() {
+        let (leading, value) = i.split_at(i.len() - mem::size_of::<u64>());
+        if leading.iter().any(|b| *b != 0) {
+            return Err(Error::AsnIntOverflow);
+        }
+        value
+    } else {
+        i
+    };
+    let mut bytes = [0u8; 8];
+    bytes[(mem::size_of::<u64>() - i.len())..].copy_from_slice(i);
+    Ok(u64::from_be_bytes(bytes))
+}
@@ -1,26 +1,43 @@
-use std::{
-    io,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
-    num::Wrapping,
-};
+use std::{io, net::SocketAddr, num::Wrapping};
 
 use crate::{
-    pdu::{self, Pdu},
-    Error, MessageType, Oid, Result, Value, Version, BUFFER_SIZE,
+    builder::SessionBuilder,
+    pdu::{self, Pdu, V1TrapInfo},
+    peers::Peers,
+    shared::SharedSession,
+    snmp,
+    table::{Table, TableState},
+    transport::{AsyncNetTransport, AsyncTransport},
+    walk::AsyncWalk,
+    Error, MessageType, Oid, Result, RetryPolicy, Value, Version,
+};
+use tokio::{
+    net::{lookup_host, ToSocketAddrs},
+    time::{self, Instant},
 };
-use tokio::net::{lookup_host, ToSocketAddrs, UdpSocket};
 
 #[cfg(feature = "v3")]
 use crate::v3;
 
 /// Asynchronous SNMP client
-pub struct AsyncSession {
+///
+/// Requests are cancellation-safe: a dropped request future leaves the session usable, as late
+/// responses to it are dropped by the next requests and the SNMPv3 security context is updated
+/// only by fully validated responses.
+///
+/// Sends its requests over UDP or TCP by default, or over any other [`AsyncTransport`] given to
+/// [`SessionBuilder::build_async_with`].
+///
+/// [`AsyncTransport`]: crate::AsyncTransport
+pub struct AsyncSession<T = AsyncNetTransport> {
     version: Version,
-    socket: UdpSocket,
+    transport: T,
     community: Vec<u8>,
     req_id: Wrapping<i32>,
+    retry: RetryPolicy,
+    max_repetitions: u32,
     send_pdu: pdu::Buf,
-    recv_buf: [u8; BUFFER_SIZE],
+    recv_buf: Vec<u8>,
     #[cfg(feature = "v3")]
     security: Option<v3::Security>,
 }
@@ -57,10 +74,11 @@ impl AsyncSession {
     where
         SA: ToSocketAddrs,
     {
-        let mut session = Self::new(Version::V3, destination, &[], starting_req_id).await?;
-        session.community = security.username.clone();
-        session.security = Some(security);
-        Ok(session)
+        let addrs = lookup_host(destination).await?.collect();
+        SessionBuilder::v3(security)
+            .with_starting_req_id(starting_req_id)
+            .connect_async(Peers::new(addrs, None))
+            .await
     }
 
     async fn new<SA>(
@@ -72,34 +90,79 @@ impl AsyncSession {
     where
         SA: ToSocketAddrs,
     {
-        let socket = match lookup_host(destination).await?.next() {
-            Some(SocketAddr::V4(addr)) => {
-                let s = UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0), 0)).await?;
-                s.connect(addr).await?;
-                s
-            }
-            Some(SocketAddr::V6(addr)) => {
-                let s = UdpSocket::bind((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 0)).await?;
-                s.connect(addr).await?;
-                s
-            }
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "No address found",
-                ))
-            }
-        };
-        Ok(Self {
-            version,
-            socket,
-            community: community.to_vec(),
-            req_id: Wrapping(starting_req_id),
+        let addrs = lookup_host(destination).await?.collect();
+        SessionBuilder::new(version, community)
+            .with_starting_req_id(starting_req_id)
+            .connect_async(Peers::new(addrs, None))
+            .await
+    }
+
+    /// Local address of the session socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+
+    /// Agent address the session currently sends its requests to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.transport.peer_addr()
+    }
+
+    /// Converts the session into a cloneable handle, which allows concurrent requests over the
+    /// session socket, connected to the current agent address. Must be called within a tokio
+    /// runtime, as the handle spawns a background receive task.
+    pub fn into_shared(self) -> SharedSession {
+        SharedSession::new(
+            self.version,
+            self.transport.into_socket(),
+            self.community,
+            self.req_id,
+            self.retry,
+            #[cfg(feature = "v3")]
+            self.security,
+        )
+    }
+}
+
+impl<T: AsyncTransport> AsyncSession<T> {
+    pub(crate) fn from_builder(builder: &SessionBuilder, transport: T) -> Self {
+        Self {
+            version: builder.version,
+            transport,
+            community: builder.community.clone(),
+            req_id: Wrapping(builder.starting_req_id),
+            retry: builder.retry,
+            max_repetitions: builder.max_repetitions,
             send_pdu: pdu::Buf::default(),
-            recv_buf: [0; BUFFER_SIZE],
+            recv_buf: vec![0; builder.max_message_size],
             #[cfg(feature = "v3")]
-            security: None,
-        })
+            security: builder.security.clone(),
+        }
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Sets the request retransmission policy. By default, requests have no timeout.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
     }
 
     #[cfg(not(feature = "v3"))]
@@ -110,25 +173,29 @@ impl AsyncSession {
 
     #[cfg(feature = "v3")]
     pub async fn init(&mut self) -> Result<()> {
-        if let Some(ref mut security) = self.security {
-            security.reset_engine_id();
-            security.reset_engine_counters();
-            // send a request to get the engine id
-            let req_id = self.req_id.0;
-            v3::build_init(req_id, &mut self.send_pdu);
-            self.req_id += Wrapping(1);
-            if let Err(e) = Pdu::from_bytes_inner(
-                Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf).await?,
-                Some(security),
-            ) {
-                if e != Error::AuthUpdated {
-                    return Err(e);
-                }
-            }
-            if security.need_init() {
-                return Err(Error::AuthFailure(v3::AuthErrorKind::NotAuthenticated));
+        if !self.security.as_ref().map_or(false, v3::Security::is_usm) {
+            return Ok(());
+        }
+        // send a request to get the engine id
+        let req_id = self.req_id.0;
+        v3::build_init(req_id, &mut self.send_pdu);
+        self.req_id += Wrapping(1);
+        let len = self.exchange(req_id).await?;
+        let Some(ref mut security) = self.security else {
+            return Ok(());
+        };
+        // the engine state is reset only when the response has been received, so cancelling the
+        // future keeps the previous state
+        security.reset_engine_id();
+        security.reset_engine_counters();
+        if let Err(e) = Pdu::from_bytes_inner(&self.recv_buf[..len], Some(security)) {
+            if e != Error::AuthUpdated {
+                return Err(e);
             }
         }
+        if security.need_init() {
+            return Err(Error::AuthFailure(v3::AuthErrorKind::NotAuthenticated));
+        }
         Ok(())
     }
 
@@ -143,61 +210,132 @@ impl AsyncSession {
         }
     }
 
+    /// Sends the request and waits for the response with the same request ID (msgID for
+    /// SNMPv3), retransmitting the request according to the retry policy. Responses with other
+    /// IDs, e.g. late responses to previous requests, are dropped.
     async fn send_and_recv<'a>(
-        socket: &UdpSocket,
+        transport: &mut T,
+        retry: &RetryPolicy,
+        req_id: i32,
         pdu: &pdu::Buf,
         out: &'a mut [u8],
     ) -> Result<&'a [u8]> {
-        if let Ok(_pdu_len) = socket.send(pdu).await {
-            match socket.recv(out).await {
-                Ok(len) => Ok(&out[..len]),
-                Err(_) => Err(Error::Receive),
+        for attempt in 0..=retry.retries {
+            if (attempt == 0 || !transport.is_reliable()) && transport.send(pdu).await.is_err() {
+                return Err(Error::Send);
+            }
+            let deadline = retry
+                .attempt_timeout(attempt)
+                .map(|timeout| Instant::now() + timeout);
+            loop {
+                let res = if let Some(deadline) = deadline {
+                    match time::timeout_at(deadline, transport.recv(out)).await {
+                        Ok(res) => res,
+                        Err(_) => break,
+                    }
+                } else {
+                    transport.recv(out).await
+                };
+                let Ok(len) = res else {
+                    return Err(Error::Receive);
+                };
+                if pdu::peek_message_id(&out[..len]).map_or(true, |id| id == req_id) {
+                    return Ok(&out[..len]);
+                }
             }
-        } else {
-            Err(Error::Send)
         }
+        Err(Error::Timeout)
+    }
+
+    /// Sends the request, and again after the transport reconnects when it can not be sent, the
+    /// agent is unreachable or does not respond. Returns the response length.
+    async fn exchange(&mut self, req_id: i32) -> Result<usize> {
+        self.transport.begin().await;
+        loop {
+            match Self::send_and_recv(
+                &mut self.transport,
+                &self.retry,
+                req_id,
+                &self.send_pdu,
+                &mut self.recv_buf,
+            )
+            .await
+            {
+                Ok(response) => return Ok(response.len()),
+                Err(e @ (Error::Send | Error::Receive | Error::Timeout)) => {
+                    if !self.transport.reconnect().await {
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Sends the message without waiting for a response, and again after the transport
+    /// reconnects when it can not be sent.
+    async fn send_unconfirmed(&mut self) -> Result<()> {
+        self.transport.begin().await;
+        while self.transport.send(&self.send_pdu).await.is_err() {
+            if !self.transport.reconnect().await {
+                return Err(Error::Send);
+            }
+        }
+        Ok(())
     }
 
     pub async fn get(&mut self, oid: &Oid<'_>) -> Result<Pdu> {
+        self.get_many(&[oid]).await
+    }
+
+    /// Sends a single GET request for all the given OIDs.
+    pub async fn get_many(&mut self, oids: &[&Oid<'_>]) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_get(
             self.version,
             self.community.as_slice(),
             req_id,
-            oid,
+            oids,
             &mut self.send_pdu,
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id).await?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf).await?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
-        self.req_id += Wrapping(1);
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
 
     pub async fn getnext(&mut self, oid: &Oid<'_>) -> Result<Pdu> {
+        self.getnext_many(&[oid]).await
+    }
+
+    /// Sends a single GETNEXT request for all the given OIDs.
+    pub async fn getnext_many(&mut self, oids: &[&Oid<'_>]) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_getnext(
             self.version,
             self.community.as_slice(),
             req_id,
-            oid,
+            oids,
             &mut self.send_pdu,
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id).await?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf).await?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
-        self.req_id += Wrapping(1);
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
@@ -210,6 +348,7 @@ impl AsyncSession {
     ) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_getbulk(
             self.version,
             self.community.as_slice(),
@@ -221,12 +360,12 @@ impl AsyncSession {
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id).await?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf).await?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
-        self.req_id += Wrapping(1);
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
@@ -234,6 +373,7 @@ impl AsyncSession {
     pub async fn set(&mut self, values: &[(&Oid<'_>, Value<'_>)]) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_set(
             self.version,
             self.community.as_slice(),
@@ -243,13 +383,138 @@ impl AsyncSession {
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id).await?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf).await?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
+        resp.validate(MessageType::Response, req_id, &self.community)?;
+        Ok(resp)
+    }
+
+    /// Sends an SNMPv1 trap, e.g. to a notification receiver on port 162. Traps are not
+    /// acknowledged, the method returns once the trap has been sent.
+    pub async fn trap_v1(
+        &mut self,
+        info: &V1TrapInfo<'_>,
+        values: &[(&Oid<'_>, Value<'_>)],
+    ) -> Result<()> {
+        if self.version != Version::V1 {
+            return Err(Error::UnsupportedVersion);
+        }
+        pdu::build_trap_v1(&self.community, info, values, &mut self.send_pdu)?;
+        self.send_unconfirmed().await
+    }
+
+    /// Sends an SNMPv2c or SNMPv3 trap, e.g. to a notification receiver on port 162. The
+    /// `sysUpTime.0` (`uptime` in hundredths of a second) and `snmpTrapOID.0` varbinds are
+    /// prepended to the values. Traps are not acknowledged, the method returns once the trap has
+    /// been sent.
+    ///
+    /// The originator of SNMPv3 traps is their authoritative engine: the security parameters
+    /// hold the local engine ID, boots and time.
+    pub async fn trap(
+        &mut self,
+        trap_oid: &Oid<'_>,
+        uptime: u32,
+        values: &[(&Oid<'_>, Value<'_>)],
+    ) -> Result<()> {
+        self.prepare();
+        let req_id = self.req_id.0;
         self.req_id += Wrapping(1);
+        pdu::build_notification(
+            self.version,
+            &self.community,
+            snmp::MSG_TRAP,
+            req_id,
+            uptime,
+            trap_oid,
+            values,
+            &mut self.send_pdu,
+            #[cfg(feature = "v3")]
+            self.security.as_ref(),
+        )?;
+        self.send_unconfirmed().await
+    }
+
+    /// Sends an SNMPv2c or SNMPv3 inform request, e.g. to a notification receiver on port 162,
+    /// and waits for the receiver to acknowledge it. The varbinds are prepended like with
+    /// [`trap`](Self::trap).
+    ///
+    /// The inform is retransmitted according to the retry policy, e.g. with
+    /// [`Backoff::Exponential`](crate::Backoff::Exponential), until the Response arrives.
+    /// [`Error::Timeout`] reports that it has not been delivered. The receiver of SNMPv3 informs
+    /// is their authoritative engine, like an agent is for requests.
+    pub async fn inform(
+        &mut self,
+        trap_oid: &Oid<'_>,
+        uptime: u32,
+        values: &[(&Oid<'_>, Value<'_>)],
+    ) -> Result<Pdu> {
+        self.prepare();
+        let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
+        pdu::build_notification(
+            self.version,
+            &self.community,
+            snmp::MSG_INFORM,
+            req_id,
+            uptime,
+            trap_oid,
+            values,
+            &mut self.send_pdu,
+            #[cfg(feature = "v3")]
+            self.security.as_ref(),
+        )?;
+        let len = self.exchange(req_id).await?;
+        let resp = Pdu::from_bytes_inner(
+            &self.recv_buf[..len],
+            #[cfg(feature = "v3")]
+            self.security.as_mut(),
+        )?;
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
+
+    /// Walks the subtree under the given OID, using GETBULK for SNMPv2c/v3 and GETNEXT for
+    /// SNMPv1. Returns a stream of the varbinds found.
+    ///
+    /// The walk stops at the end of the subtree or the MIB view. An agent which returns OIDs out
+    /// of order stops it with [`Error::OidNotIncreasing`].
+    pub fn walk(&mut self, oid: &Oid) -> AsyncWalk<'_> {
+        let max_repetitions = self.max_repetitions;
+        AsyncWalk::new(self, oid, max_repetitions)
+    }
+
+    /// Fetches the given columns of a conceptual table, `entry` is the table entry OID (e.g.
+    /// ifEntry `1.3.6.1.2.1.2.2.1`).
+    ///
+    /// The columns are requested in parallel with GETBULK for SNMPv2c/v3 and GETNEXT for SNMPv1,
+    /// the values are assembled into rows by their instance index.
+    pub async fn table(&mut self, entry: &Oid<'_>, columns: &[u32]) -> Result<Table> {
+        let mut state = TableState::new(entry, columns)?;
+        while let Some(oids) = state.next_oids() {
+            let res = self.table_request(&mut state, &oids).await;
+            // the security context has been updated, the request is safe to repeat
+            #[cfg(feature = "v3")]
+            let res = if res == Err(Error::AuthUpdated) {
+                self.table_request(&mut state, &oids).await
+            } else {
+                res
+            };
+            res?;
+        }
+        Ok(state.into_table())
+    }
+
+    async fn table_request(&mut self, state: &mut TableState, oids: &[Oid<'_>]) -> Result<()> {
+        let oids = oids.iter().collect::<Vec<_>>();
+        let pdu = if self.version == Version::V1 {
+            self.getnext_many(&oids).await?
+        } else {
+            self.getbulk(&oids, 0, self.max_repetitions).await?
+        };
+        state.feed(&pdu)
+    }
 }
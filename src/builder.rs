@@ -0,0 +1,238 @@
+use std::{io, net::SocketAddr, time::Duration};
+
+#[cfg(feature = "tokio")]
+use crate::{
+    peers::AsyncResolve,
+    transport::{AsyncNetTransport, AsyncTransport},
+    AsyncSession,
+};
+use crate::{
+    peers::{Peers, Resolve},
+    socket::{Protocol, SocketConfig},
+    transport::{NetTransport, Transport},
+    walk::DEFAULT_MAX_REPETITIONS,
+    RetryPolicy, SyncSession, Version, BUFFER_SIZE,
+};
+
+#[cfg(feature = "v3")]
+use crate::v3;
+
+/// Minimal message size, which all SNMP entities must accept (RFC 3417).
+const MIN_MESSAGE_SIZE: usize = 484;
+
+/// Session configuration, builds both [`SyncSession`] and [`AsyncSession`] from the same
+/// settings.
+///
+/// [`AsyncSession`]: crate::AsyncSession
+#[derive(Debug, Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SessionBuilder {
+    pub(crate) version: Version,
+    pub(crate) community: Vec<u8>,
+    #[cfg(feature = "v3")]
+    pub(crate) security: Option<v3::Security>,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) starting_req_id: i32,
+    pub(crate) max_repetitions: u32,
+    pub(crate) max_message_size: usize,
+    pub(crate) socket: SocketConfig,
+}
+
+impl SessionBuilder {
+    pub fn v1(community: &[u8]) -> Self {
+        Self::new(Version::V1, community)
+    }
+
+    pub fn v2c(community: &[u8]) -> Self {
+        Self::new(Version::V2C, community)
+    }
+
+    #[cfg(feature = "v3")]
+    pub fn v3(security: v3::Security) -> Self {
+        let mut builder = Self::new(Version::V3, &security.username);
+        builder.security = Some(security);
+        builder
+    }
+
+    pub(crate) fn new(version: Version, community: &[u8]) -> Self {
+        Self {
+            version,
+            community: community.to_vec(),
+            #[cfg(feature = "v3")]
+            security: None,
+            retry: RetryPolicy::default(),
+            starting_req_id: 0,
+            max_repetitions: DEFAULT_MAX_REPETITIONS,
+            max_message_size: BUFFER_SIZE,
+            socket: SocketConfig::default(),
+        }
+    }
+
+    /// Request timeout and retransmissions. By default, requests have no timeout.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_starting_req_id(mut self, starting_req_id: i32) -> Self {
+        self.starting_req_id = starting_req_id;
+        self
+    }
+
+    /// Max-repetitions of GETBULK requests sent by walks and table fetches (default: 10).
+    pub fn with_max_repetitions(mut self, max_repetitions: u32) -> Self {
+        self.max_repetitions = max_repetitions;
+        self
+    }
+
+    /// The largest response message the session accepts, in bytes (default and maximum: 65507,
+    /// minimum: 484). Larger responses fail to decode.
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size.clamp(MIN_MESSAGE_SIZE, BUFFER_SIZE);
+        self
+    }
+
+    /// Local address to bind the session socket to. By default, the socket is bound to the
+    /// unspecified address of the agent address family and a random port.
+    pub fn with_local_addr(mut self, local_addr: SocketAddr) -> Self {
+        self.socket.local_addr = Some(local_addr);
+        self
+    }
+
+    /// Network interface or VRF to bind the session socket to (`SO_BINDTODEVICE`), e.g. `eth1`.
+    /// Usually requires `CAP_NET_RAW`.
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub fn with_bind_device(mut self, interface: &str) -> Self {
+        self.socket.bind_device = Some(interface.as_bytes().to_vec());
+        self
+    }
+
+    /// Kernel receive buffer size of the session socket (`SO_RCVBUF`).
+    pub fn with_recv_buffer_size(mut self, recv_buffer_size: usize) -> Self {
+        self.socket.recv_buffer_size = Some(recv_buffer_size);
+        self
+    }
+
+    /// Builds a synchronous session for the agent at the given destination.
+    ///
+    /// All addresses the destination resolves to are used, alternating between IPv6 and IPv4: when
+    /// a request cannot be sent to the current address or times out, it is sent to the next one,
+    /// and the session stays with the first address that responds. The addresses are tried one
+    /// after another, each one for a full retry cycle.
+    ///
+    /// The destination is resolved only when the session is built, see
+    /// [`SessionBuilder::build_sync_resolving`] for agents whose DNS records change.
+    pub fn build_sync<SA: std::net::ToSocketAddrs>(
+        &self,
+        destination: SA,
+    ) -> io::Result<SyncSession> {
+        let addrs = destination.to_socket_addrs()?.collect();
+        self.connect_sync(Peers::new(addrs, None))
+    }
+
+    /// Builds a synchronous session like [`SessionBuilder::build_sync`], which resolves the
+    /// destination again when the given interval has elapsed since the last resolution, before
+    /// the next request. Allows to follow agents whose DNS records change.
+    pub fn build_sync_resolving<SA>(
+        &self,
+        destination: SA,
+        interval: Duration,
+    ) -> io::Result<SyncSession>
+    where
+        SA: std::net::ToSocketAddrs + Send + Sync + 'static,
+    {
+        let addrs = destination.to_socket_addrs()?.collect();
+        let resolve: Resolve = Box::new(move || Ok(destination.to_socket_addrs()?.collect()));
+        self.connect_sync(Peers::new(addrs, Some((resolve, interval))))
+    }
+
+    /// Builds an asynchronous session for the agent at the given destination.
+    ///
+    /// Uses all addresses the destination resolves to, see [`SessionBuilder::build_sync`].
+    #[cfg(feature = "tokio")]
+    pub async fn build_async<SA: tokio::net::ToSocketAddrs>(
+        &self,
+        destination: SA,
+    ) -> io::Result<AsyncSession> {
+        let addrs = tokio::net::lookup_host(destination).await?.collect();
+        self.connect_async(Peers::new(addrs, None)).await
+    }
+
+    /// Builds an asynchronous session which resolves the destination again periodically, see
+    /// [`SessionBuilder::build_sync_resolving`].
+    #[cfg(feature = "tokio")]
+    pub async fn build_async_resolving<SA>(
+        &self,
+        destination: SA,
+        interval: Duration,
+    ) -> io::Result<AsyncSession>
+    where
+        SA: tokio::net::ToSocketAddrs + Clone + Send + Sync + 'static,
+    {
+        let addrs = tokio::net::lookup_host(destination.clone())
+            .await?
+            .collect();
+        let resolve: AsyncResolve = Box::new(move || {
+            let destination = destination.clone();
+            Box::pin(async move { Ok(tokio::net::lookup_host(destination).await?.collect()) })
+        });
+        self.connect_async(Peers::new(addrs, Some((resolve, interval))))
+            .await
+    }
+
+    /// Builds a synchronous session for the agent at the given destination, which sends its
+    /// requests over TCP (RFC 3430). Requests are not retransmitted over the connection, a
+    /// timed out or broken connection is established again.
+    pub fn build_sync_tcp<SA: std::net::ToSocketAddrs>(
+        &self,
+        destination: SA,
+    ) -> io::Result<SyncSession> {
+        self.with_protocol(Protocol::Tcp).build_sync(destination)
+    }
+
+    /// Builds an asynchronous session for the agent at the given destination, which sends its
+    /// requests over TCP (RFC 3430), see [`SessionBuilder::build_sync_tcp`].
+    #[cfg(feature = "tokio")]
+    pub async fn build_async_tcp<SA: tokio::net::ToSocketAddrs>(
+        &self,
+        destination: SA,
+    ) -> io::Result<AsyncSession> {
+        self.with_protocol(Protocol::Tcp)
+            .build_async(destination)
+            .await
+    }
+
+    fn with_protocol(&self, protocol: Protocol) -> Self {
+        let mut builder = self.clone();
+        builder.socket.protocol = protocol;
+        builder
+    }
+
+    /// Builds a synchronous session which sends its requests over the given transport.
+    pub fn build_sync_with<T: Transport>(&self, transport: T) -> SyncSession<T> {
+        SyncSession::from_builder(self, transport)
+    }
+
+    /// Builds an asynchronous session which sends its requests over the given transport.
+    #[cfg(feature = "tokio")]
+    pub fn build_async_with<T: AsyncTransport>(&self, transport: T) -> AsyncSession<T> {
+        AsyncSession::from_builder(self, transport)
+    }
+
+    /// Connects a synchronous session to the first reachable address.
+    pub(crate) fn connect_sync(&self, peers: Peers<Resolve>) -> io::Result<SyncSession> {
+        let transport = NetTransport::connect(self.socket.clone(), peers, self.retry.timeout)?;
+        Ok(self.build_sync_with(transport))
+    }
+
+    /// Connects an asynchronous session to the first reachable address.
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn connect_async(
+        &self,
+        peers: Peers<AsyncResolve>,
+    ) -> io::Result<AsyncSession> {
+        let transport =
+            AsyncNetTransport::connect(self.socket.clone(), peers, self.retry.timeout).await?;
+        Ok(self.build_async_with(transport))
+    }
+}
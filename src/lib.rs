@@ -5,6 +5,8 @@ use std::fmt;
 
 pub mod asn1;
 pub use asn1::AsnReader;
+mod builder;
+pub use builder::SessionBuilder;
 #[cfg(feature = "mibs")]
 pub mod mibs;
 pub mod pdu;
@@ -13,12 +15,45 @@ mod syncsession;
 #[cfg(feature = "v3")]
 pub mod v3;
 pub use syncsession::SyncSession;
+mod peers;
+mod retry;
+mod socket;
+pub use retry::{Backoff, RetryPolicy};
+mod table;
+mod tcp;
+#[cfg(feature = "v3")]
+pub mod tls;
+mod transport;
+mod trap;
+pub use table::Table;
+#[cfg(feature = "tokio")]
+pub use transport::{AsyncNetTransport, AsyncTransport, BoxFuture};
+pub use transport::{NetTransport, Transport};
+#[cfg(feature = "v3")]
+pub use trap::DtlsTrapListener;
+#[cfg(feature = "tokio")]
+pub use trap::{AsyncTrapListener, Notification, NotificationStream};
+pub use trap::{ShutdownHandle, TrapListener};
+mod walk;
+#[cfg(feature = "tokio")]
+pub use walk::AsyncWalk;
+pub use walk::Walk;
 #[cfg(feature = "tokio")]
 mod asyncsession;
 #[cfg(feature = "tokio")]
 pub use asyncsession::AsyncSession;
+#[cfg(feature = "tokio")]
+mod pending;
+#[cfg(feature = "tokio")]
+mod poller;
+#[cfg(feature = "tokio")]
+pub use poller::{Poller, Target};
+#[cfg(feature = "tokio")]
+mod shared;
+#[cfg(feature = "tokio")]
+pub use shared::SharedSession;
 
-pub use pdu::Pdu;
+pub use pdu::{OwnedPdu, Pdu};
 
 pub use asn1_rs::Oid;
 
@@ -91,6 +126,12 @@ pub enum Error {
     ValueOutOfRange,
     /// Buffer overflow.
     BufferOverflow,
+    /// The agent replied with a non-zero error status.
+    ErrorStatus { status: u32, index: u32 },
+    /// The agent returned OIDs which are not increasing (e.g. looping agent).
+    OidNotIncreasing,
+    /// Varbind decoding error at the given offset of the varbind list.
+    VarbindDecode { offset: usize, error: Box<Error> },
 
     /// Authentication failure
     #[cfg(feature = "v3")]
@@ -106,6 +147,8 @@ pub enum Error {
     Send,
     /// Socket receive error.
     Receive,
+    /// No response received after all retransmissions.
+    Timeout,
     /// MIB errors
     Mib(String),
 }
@@ -124,6 +167,13 @@ impl fmt::Display for Error {
             Error::CommunityMismatch => write!(f, "Community string mismatch"),
             Error::ValueOutOfRange => write!(f, "Value out of range"),
             Error::BufferOverflow => write!(f, "Buffer overflow"),
+            Error::ErrorStatus { status, index } => {
+                write!(f, "Error status {} at varbind index {}", status, index)
+            }
+            Error::OidNotIncreasing => write!(f, "OIDs are not increasing"),
+            Error::VarbindDecode { offset, error } => {
+                write!(f, "Varbind decoding error at offset {}: {}", offset, error)
+            }
             #[cfg(feature = "v3")]
             Error::AuthFailure(err) => write!(f, "Authentication failure: {}", err),
             #[cfg(feature = "v3")]
@@ -134,6 +184,7 @@ impl fmt::Display for Error {
             }
             Error::Send => write!(f, "Socket send error"),
             Error::Receive => write!(f, "Socket receive error"),
+            Error::Timeout => write!(f, "Request timed out"),
             Error::Mib(ref s) => write!(f, "MIB error: {}", s),
         }
     }
@@ -158,6 +209,7 @@ type Result<T> = std::result::Result<T, Error>;
 
 const BUFFER_SIZE: usize = 65_507;
 
+#[derive(Clone)]
 pub enum Value<'a> {
     Boolean(bool),
     Null,
@@ -225,63 +277,199 @@ impl fmt::Debug for Value<'_> {
     }
 }
 
+impl Value<'_> {
+    /// Copies the value into an [`OwnedValue`] which does not borrow the receive buffer.
+    pub fn to_owned_value(&self) -> OwnedValue {
+        match *self {
+            Value::Boolean(v) => OwnedValue::Boolean(v),
+            Value::Null => OwnedValue::Null,
+            Value::Integer(n) => OwnedValue::Integer(n),
+            Value::OctetString(slice) => OwnedValue::OctetString(slice.to_vec()),
+            Value::ObjectIdentifier(ref obj_id) => OwnedValue::ObjectIdentifier(obj_id.to_owned()),
+            Value::Sequence(ref val) => OwnedValue::Sequence(val.as_bytes().to_vec()),
+            Value::Set(ref val) => OwnedValue::Set(val.as_bytes().to_vec()),
+            Value::Constructed(ident, ref val) => {
+                OwnedValue::Constructed(ident, val.as_bytes().to_vec())
+            }
+            Value::IpAddress(val) => OwnedValue::IpAddress(val),
+            Value::Counter32(val) => OwnedValue::Counter32(val),
+            Value::Unsigned32(val) => OwnedValue::Unsigned32(val),
+            Value::Timeticks(val) => OwnedValue::Timeticks(val),
+            Value::Opaque(val) => OwnedValue::Opaque(val.to_vec()),
+            Value::Counter64(val) => OwnedValue::Counter64(val),
+            Value::EndOfMibView => OwnedValue::EndOfMibView,
+            Value::NoSuchObject => OwnedValue::NoSuchObject,
+            Value::NoSuchInstance => OwnedValue::NoSuchInstance,
+            Value::GetRequest(ref val) => OwnedValue::GetRequest(val.as_bytes().to_vec()),
+            Value::GetNextRequest(ref val) => OwnedValue::GetNextRequest(val.as_bytes().to_vec()),
+            Value::GetBulkRequest(ref val) => OwnedValue::GetBulkRequest(val.as_bytes().to_vec()),
+            Value::Response(ref val) => OwnedValue::Response(val.as_bytes().to_vec()),
+            Value::SetRequest(ref val) => OwnedValue::SetRequest(val.as_bytes().to_vec()),
+            Value::InformRequest(ref val) => OwnedValue::InformRequest(val.as_bytes().to_vec()),
+            Value::Trap(ref val) => OwnedValue::Trap(val.as_bytes().to_vec()),
+            Value::Report(ref val) => OwnedValue::Report(val.as_bytes().to_vec()),
+        }
+    }
+}
+
+/// Owned version of [`Value`], can be kept after the session receives the next response or sent
+/// to another thread.
+///
+/// Constructed values keep their raw contents, which can be read with [`OwnedValue::as_value`].
+#[derive(Clone, PartialEq, Eq)]
+pub enum OwnedValue {
+    Boolean(bool),
+    Null,
+    Integer(i64),
+    OctetString(Vec<u8>),
+    ObjectIdentifier(Oid<'static>),
+    Sequence(Vec<u8>),
+    Set(Vec<u8>),
+    Constructed(u8, Vec<u8>),
+
+    IpAddress([u8; 4]),
+    Counter32(u32),
+    Unsigned32(u32),
+    Timeticks(u32),
+    Opaque(Vec<u8>),
+    Counter64(u64),
+
+    EndOfMibView,
+    NoSuchObject,
+    NoSuchInstance,
+
+    GetRequest(Vec<u8>),
+    GetNextRequest(Vec<u8>),
+    GetBulkRequest(Vec<u8>),
+    Response(Vec<u8>),
+    SetRequest(Vec<u8>),
+    InformRequest(Vec<u8>),
+    Trap(Vec<u8>),
+    Report(Vec<u8>),
+}
+
+impl OwnedValue {
+    /// Borrows the value as [`Value`], e.g. to pass it to `set` requests.
+    pub fn as_value(&self) -> Value<'_> {
+        match *self {
+            OwnedValue::Boolean(v) => Value::Boolean(v),
+            OwnedValue::Null => Value::Null,
+            OwnedValue::Integer(n) => Value::Integer(n),
+            OwnedValue::OctetString(ref v) => Value::OctetString(v),
+            OwnedValue::ObjectIdentifier(ref obj_id) => Value::ObjectIdentifier(obj_id.clone()),
+            OwnedValue::Sequence(ref v) => Value::Sequence(AsnReader::from_bytes(v)),
+            OwnedValue::Set(ref v) => Value::Set(AsnReader::from_bytes(v)),
+            OwnedValue::Constructed(ident, ref v) => {
+                Value::Constructed(ident, AsnReader::from_bytes(v))
+            }
+            OwnedValue::IpAddress(val) => Value::IpAddress(val),
+            OwnedValue::Counter32(val) => Value::Counter32(val),
+            OwnedValue::Unsigned32(val) => Value::Unsigned32(val),
+            OwnedValue::Timeticks(val) => Value::Timeticks(val),
+            OwnedValue::Opaque(ref v) => Value::Opaque(v),
+            OwnedValue::Counter64(val) => Value::Counter64(val),
+            OwnedValue::EndOfMibView => Value::EndOfMibView,
+            OwnedValue::NoSuchObject => Value::NoSuchObject,
+            OwnedValue::NoSuchInstance => Value::NoSuchInstance,
+            OwnedValue::GetRequest(ref v) => Value::GetRequest(AsnReader::from_bytes(v)),
+            OwnedValue::GetNextRequest(ref v) => Value::GetNextRequest(AsnReader::from_bytes(v)),
+            OwnedValue::GetBulkRequest(ref v) => Value::GetBulkRequest(AsnReader::from_bytes(v)),
+            OwnedValue::Response(ref v) => Value::Response(AsnReader::from_bytes(v)),
+            OwnedValue::SetRequest(ref v) => Value::SetRequest(AsnReader::from_bytes(v)),
+            OwnedValue::InformRequest(ref v) => Value::InformRequest(AsnReader::from_bytes(v)),
+            OwnedValue::Trap(ref v) => Value::Trap(AsnReader::from_bytes(v)),
+            OwnedValue::Report(ref v) => Value::Report(AsnReader::from_bytes(v)),
+        }
+    }
+}
+
+impl fmt::Debug for OwnedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_value().fmt(f)
+    }
+}
+
+impl<'a> From<&'a OwnedValue> for Value<'a> {
+    fn from(value: &'a OwnedValue) -> Self {
+        value.as_value()
+    }
+}
+
+impl From<Value<'_>> for OwnedValue {
+    fn from(value: Value<'_>) -> Self {
+        value.to_owned_value()
+    }
+}
+
+impl<'a> AsnReader<'a> {
+    /// Reads the next value of any supported type.
+    pub fn read_value(&mut self) -> Result<Value<'a>> {
+        let ident = self.peek_byte()?;
+        match ident {
+            asn1::TYPE_BOOLEAN => self.read_asn_boolean().map(Value::Boolean),
+            asn1::TYPE_NULL => self.read_asn_null().map(|()| Value::Null),
+            asn1::TYPE_INTEGER => self.read_asn_integer().map(Value::Integer),
+            asn1::TYPE_OCTETSTRING => self.read_asn_octetstring().map(Value::OctetString),
+            asn1::TYPE_OBJECTIDENTIFIER => self
+                .read_asn_objectidentifier()
+                .map(Value::ObjectIdentifier),
+            asn1::TYPE_SEQUENCE => self
+                .read_raw(ident)
+                .map(|v| Value::Sequence(AsnReader::from_bytes(v))),
+            asn1::TYPE_SET => self
+                .read_raw(ident)
+                .map(|v| Value::Set(AsnReader::from_bytes(v))),
+            snmp::TYPE_IPADDRESS => self.read_snmp_ipaddress().map(Value::IpAddress),
+            snmp::TYPE_COUNTER32 => self.read_snmp_counter32().map(Value::Counter32),
+            snmp::TYPE_UNSIGNED32 => self.read_snmp_unsigned32().map(Value::Unsigned32),
+            snmp::TYPE_TIMETICKS => self.read_snmp_timeticks().map(Value::Timeticks),
+            snmp::TYPE_OPAQUE => self.read_snmp_opaque().map(Value::Opaque),
+            snmp::TYPE_COUNTER64 => self.read_snmp_counter64().map(Value::Counter64),
+            snmp::SNMP_NOSUCHOBJECT => self.read_snmp_nosuchobject().map(|()| Value::NoSuchObject),
+            snmp::SNMP_NOSUCHINSTANCE => self
+                .read_snmp_nosuchinstance()
+                .map(|()| Value::NoSuchInstance),
+            snmp::SNMP_ENDOFMIBVIEW => self.read_snmp_endofmibview().map(|()| Value::EndOfMibView),
+            snmp::MSG_GET => self
+                .read_raw(ident)
+                .map(|v| Value::GetRequest(AsnReader::from_bytes(v))),
+            snmp::MSG_GET_NEXT => self
+                .read_raw(ident)
+                .map(|v| Value::GetNextRequest(AsnReader::from_bytes(v))),
+            snmp::MSG_GET_BULK => self
+                .read_raw(ident)
+                .map(|v| Value::GetBulkRequest(AsnReader::from_bytes(v))),
+            snmp::MSG_RESPONSE => self
+                .read_raw(ident)
+                .map(|v| Value::Response(AsnReader::from_bytes(v))),
+            snmp::MSG_SET => self
+                .read_raw(ident)
+                .map(|v| Value::SetRequest(AsnReader::from_bytes(v))),
+            snmp::MSG_INFORM => self
+                .read_raw(ident)
+                .map(|v| Value::InformRequest(AsnReader::from_bytes(v))),
+            snmp::MSG_TRAP => self
+                .read_raw(ident)
+                .map(|v| Value::Trap(AsnReader::from_bytes(v))),
+            snmp::MSG_REPORT => self
+                .read_raw(ident)
+                .map(|v| Value::Report(AsnReader::from_bytes(v))),
+            ident if ident & asn1::CONSTRUCTED == asn1::CONSTRUCTED => self
+                .read_raw(ident)
+                .map(|v| Value::Constructed(ident, AsnReader::from_bytes(v))),
+            _ => Err(Error::AsnUnsupportedType),
+        }
+    }
+}
+
 impl<'a> Iterator for AsnReader<'a> {
     type Item = Value<'a>;
 
     fn next(&mut self) -> Option<Value<'a>> {
-        if let Ok(ident) = self.peek_byte() {
-            let ret: Result<Value> = match ident {
-                asn1::TYPE_BOOLEAN => self.read_asn_boolean().map(Value::Boolean),
-                asn1::TYPE_NULL => self.read_asn_null().map(|()| Value::Null),
-                asn1::TYPE_INTEGER => self.read_asn_integer().map(Value::Integer),
-                asn1::TYPE_OCTETSTRING => self.read_asn_octetstring().map(Value::OctetString),
-                asn1::TYPE_OBJECTIDENTIFIER => self
-                    .read_asn_objectidentifier()
-                    .map(Value::ObjectIdentifier),
-                asn1::TYPE_SEQUENCE => self
-                    .read_raw(ident)
-                    .map(|v| Value::Sequence(AsnReader::from_bytes(v))),
-                asn1::TYPE_SET => self
-                    .read_raw(ident)
-                    .map(|v| Value::Set(AsnReader::from_bytes(v))),
-                snmp::TYPE_IPADDRESS => self.read_snmp_ipaddress().map(Value::IpAddress),
-                snmp::TYPE_COUNTER32 => self.read_snmp_counter32().map(Value::Counter32),
-                snmp::TYPE_UNSIGNED32 => self.read_snmp_unsigned32().map(Value::Unsigned32),
-                snmp::TYPE_TIMETICKS => self.read_snmp_timeticks().map(Value::Timeticks),
-                snmp::TYPE_OPAQUE => self.read_snmp_opaque().map(Value::Opaque),
-                snmp::TYPE_COUNTER64 => self.read_snmp_counter64().map(Value::Counter64),
-                snmp::MSG_GET => self
-                    .read_raw(ident)
-                    .map(|v| Value::GetRequest(AsnReader::from_bytes(v))),
-                snmp::MSG_GET_NEXT => self
-                    .read_raw(ident)
-                    .map(|v| Value::GetNextRequest(AsnReader::from_bytes(v))),
-                snmp::MSG_GET_BULK => self
-                    .read_raw(ident)
-                    .map(|v| Value::GetBulkRequest(AsnReader::from_bytes(v))),
-                snmp::MSG_RESPONSE => self
-                    .read_raw(ident)
-                    .map(|v| Value::Response(AsnReader::from_bytes(v))),
-                snmp::MSG_SET => self
-                    .read_raw(ident)
-                    .map(|v| Value::SetRequest(AsnReader::from_bytes(v))),
-                snmp::MSG_INFORM => self
-                    .read_raw(ident)
-                    .map(|v| Value::InformRequest(AsnReader::from_bytes(v))),
-                snmp::MSG_TRAP => self
-                    .read_raw(ident)
-                    .map(|v| Value::Trap(AsnReader::from_bytes(v))),
-                snmp::MSG_REPORT => self
-                    .read_raw(ident)
-                    .map(|v| Value::Report(AsnReader::from_bytes(v))),
-                ident if ident & asn1::CONSTRUCTED == asn1::CONSTRUCTED => self
-                    .read_raw(ident)
-                    .map(|v| Value::Constructed(ident, AsnReader::from_bytes(v))),
-                _ => Err(Error::AsnUnsupportedType),
-            };
-            ret.ok()
-        } else {
+        if self.bytes_left() == 0 {
             None
+        } else {
+            self.read_value().ok()
         }
     }
 }
@@ -319,6 +507,7 @@ impl MessageType {
 #[derive(Clone)]
 pub struct Varbinds<'a> {
     inner: AsnReader<'a>,
+    len: usize,
 }
 
 impl fmt::Debug for Varbinds<'_> {
@@ -336,7 +525,57 @@ impl<'a> Varbinds<'a> {
     pub fn from_bytes(bytes: &'a [u8]) -> Varbinds<'a> {
         Varbinds {
             inner: AsnReader::from_bytes(bytes),
+            len: bytes.len(),
+        }
+    }
+
+    /// Returns a fallible iterator over the remaining varbinds.
+    ///
+    /// Unlike the plain iterator, which silently stops at the first varbind it can not decode,
+    /// this one yields [`Error::VarbindDecode`] with the offset of the broken varbind and then
+    /// ends.
+    pub fn try_iter(&self) -> TryVarbinds<'a> {
+        TryVarbinds {
+            inner: self.inner.clone(),
+            len: self.len,
+            done: false,
+        }
+    }
+}
+
+/// Fallible varbind iterator, see [`Varbinds::try_iter`].
+#[derive(Clone)]
+pub struct TryVarbinds<'a> {
+    inner: AsnReader<'a>,
+    len: usize,
+    done: bool,
+}
+
+impl<'a> TryVarbinds<'a> {
+    fn read_varbind(&mut self) -> Result<(Oid<'a>, Value<'a>)> {
+        let seq = self.inner.read_raw(asn1::TYPE_SEQUENCE)?;
+        let mut pair = AsnReader::from_bytes(seq);
+        let name = pair.read_asn_objectidentifier()?;
+        let value = pair.read_value()?;
+        Ok((name, value))
+    }
+}
+
+impl<'a> Iterator for TryVarbinds<'a> {
+    type Item = Result<(Oid<'a>, Value<'a>)>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.inner.bytes_left() == 0 {
+            return None;
         }
+        let offset = self.len - self.inner.bytes_left();
+        let res = self.read_varbind().map_err(|e| {
+            self.done = true;
+            Error::VarbindDecode {
+                offset,
+                error: Box::new(e),
+            }
+        });
+        Some(res)
     }
 }
 
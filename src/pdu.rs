@@ -2,7 +2,7 @@
 use crate::v3;
 use crate::{
     asn1::{self, AsnReader},
-    snmp, Error, MessageType, Oid, Result, Value, Varbinds, Version, BUFFER_SIZE,
+    snmp, Error, MessageType, Oid, OwnedValue, Result, Value, Varbinds, Version, BUFFER_SIZE,
 };
 use std::{
     fmt, mem,
@@ -147,19 +147,19 @@ impl Buf {
     }
 
     pub fn push_counter32(&mut self, n: u32) {
-        let len = self.push_i64(i64::from(n));
+        let len = self.push_u64(u64::from(n));
         self.push_length(len);
         self.push_byte(snmp::TYPE_COUNTER32);
     }
 
     pub fn push_unsigned32(&mut self, n: u32) {
-        let len = self.push_i64(i64::from(n));
+        let len = self.push_u64(u64::from(n));
         self.push_length(len);
         self.push_byte(snmp::TYPE_UNSIGNED32);
     }
 
     pub fn push_timeticks(&mut self, n: u32) {
-        let len = self.push_i64(i64::from(n));
+        let len = self.push_u64(u64::from(n));
         self.push_length(len);
         self.push_byte(snmp::TYPE_TIMETICKS);
     }
@@ -170,13 +170,31 @@ impl Buf {
         self.push_byte(snmp::TYPE_OPAQUE);
     }
 
-    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
     pub fn push_counter64(&mut self, n: u64) {
-        let len = self.push_i64(n as i64);
+        let len = self.push_u64(n);
         self.push_length(len);
         self.push_byte(snmp::TYPE_COUNTER64);
     }
 
+    /// Pushes the content octets of an unsigned integer, prepending a zero octet if the most
+    /// significant bit is set, so the value is not read back as negative.
+    pub fn push_u64(&mut self, n: u64) -> usize {
+        let bytes = n.to_be_bytes();
+        let num_null_bytes = (n.leading_zeros() / 8) as usize;
+        let count = (bytes.len() - num_null_bytes).max(1);
+        let value = &bytes[bytes.len() - count..];
+        if self.available().len() <= count {
+            return 0;
+        }
+        self.push_chunk(value);
+        if value[0] > 127 {
+            self.push_byte(0);
+            count + 1
+        } else {
+            count
+        }
+    }
+
     pub fn push_i64(&mut self, mut n: i64) -> usize {
         let (null, num_null_bytes) = if n.is_negative() {
             (0xffu8, ((!n).leading_zeros() / 8) as usize)
@@ -310,6 +328,7 @@ pub(crate) fn build(
         return v3::build(
             ident,
             req_id,
+            req_id,
             values,
             non_repeaters,
             max_repetitions,
@@ -336,43 +355,48 @@ pub(crate) fn build_inner(
     buf: &mut Buf,
 ) {
     buf.push_constructed(ident, |buf| {
-        buf.push_sequence(|buf| {
-            for &(oid, ref val) in values.iter().rev() {
-                buf.push_sequence(|buf| {
-                    match *val {
-                        Value::Boolean(b) => buf.push_boolean(b),
-                        Value::Null => buf.push_null(),
-                        Value::Integer(i) => buf.push_integer(i),
-                        Value::OctetString(ostr) => buf.push_octet_string(ostr),
-                        Value::ObjectIdentifier(ref objid) => {
-                            buf.push_object_identifier_raw(objid.as_bytes());
-                        }
-                        Value::IpAddress(ip) => buf.push_ipaddress(ip),
-                        Value::Counter32(i) => buf.push_counter32(i),
-                        Value::Unsigned32(i) => buf.push_unsigned32(i),
-                        Value::Timeticks(tt) => buf.push_timeticks(tt),
-                        Value::Opaque(bytes) => buf.push_opaque(bytes),
-                        Value::Counter64(i) => buf.push_counter64(i),
-                        Value::EndOfMibView => buf.push_endofmibview(),
-                        Value::NoSuchObject => buf.push_nosuchobject(),
-                        Value::NoSuchInstance => buf.push_nosuchinstance(),
-                        _ => return,
-                    }
-                    buf.push_object_identifier_raw(oid.as_bytes());
-                });
-            }
-        });
+        push_varbinds(values, buf);
         buf.push_integer(non_repeaters.into());
         buf.push_integer(max_repetitions.into());
         buf.push_integer(i64::from(req_id));
     });
 }
 
+/// Encodes the variable bindings, values which can not be sent are skipped.
+fn push_varbinds(values: &[(&Oid, Value)], buf: &mut Buf) {
+    buf.push_sequence(|buf| {
+        for &(oid, ref val) in values.iter().rev() {
+            buf.push_sequence(|buf| {
+                match *val {
+                    Value::Boolean(b) => buf.push_boolean(b),
+                    Value::Null => buf.push_null(),
+                    Value::Integer(i) => buf.push_integer(i),
+                    Value::OctetString(ostr) => buf.push_octet_string(ostr),
+                    Value::ObjectIdentifier(ref objid) => {
+                        buf.push_object_identifier_raw(objid.as_bytes());
+                    }
+                    Value::IpAddress(ip) => buf.push_ipaddress(ip),
+                    Value::Counter32(i) => buf.push_counter32(i),
+                    Value::Unsigned32(i) => buf.push_unsigned32(i),
+                    Value::Timeticks(tt) => buf.push_timeticks(tt),
+                    Value::Opaque(bytes) => buf.push_opaque(bytes),
+                    Value::Counter64(i) => buf.push_counter64(i),
+                    Value::EndOfMibView => buf.push_endofmibview(),
+                    Value::NoSuchObject => buf.push_nosuchobject(),
+                    Value::NoSuchInstance => buf.push_nosuchinstance(),
+                    _ => return,
+                }
+                buf.push_object_identifier_raw(oid.as_bytes());
+            });
+        }
+    });
+}
+
 pub(crate) fn build_get(
     version: Version,
     community: &[u8],
     req_id: i32,
-    oid: &Oid,
+    oids: &[&Oid],
     buf: &mut Buf,
     #[cfg(feature = "v3")] security: Option<&v3::Security>,
 ) -> Result<()> {
@@ -381,7 +405,10 @@ pub(crate) fn build_get(
         community,
         snmp::MSG_GET,
         req_id,
-        &[(oid, Value::Null)],
+        oids.iter()
+            .map(|&oid| (oid, Value::Null))
+            .collect::<Vec<_>>()
+            .as_slice(),
         0,
         0,
         buf,
@@ -394,7 +421,7 @@ pub(crate) fn build_getnext(
     version: Version,
     community: &[u8],
     req_id: i32,
-    oid: &Oid,
+    oids: &[&Oid],
     buf: &mut Buf,
     #[cfg(feature = "v3")] security: Option<&v3::Security>,
 ) -> Result<()> {
@@ -403,7 +430,10 @@ pub(crate) fn build_getnext(
         community,
         snmp::MSG_GET_NEXT,
         req_id,
-        &[(oid, Value::Null)],
+        oids.iter()
+            .map(|&oid| (oid, Value::Null))
+            .collect::<Vec<_>>()
+            .as_slice(),
         0,
         0,
         buf,
@@ -462,6 +492,130 @@ pub(crate) fn build_set(
     )
 }
 
+/// Builds an SNMPv1 Trap-PDU (RFC 1157), which carries the trap information instead of the
+/// request ID and error fields. The agent address must be an IPv4 address.
+pub(crate) fn build_trap_v1(
+    community: &[u8],
+    info: &V1TrapInfo,
+    values: &[(&Oid, Value)],
+    buf: &mut Buf,
+) -> Result<()> {
+    let IpAddr::V4(agent_addr) = info.agent_addr else {
+        return Err(Error::ValueOutOfRange);
+    };
+    buf.reset();
+    buf.push_sequence(|buf| {
+        buf.push_constructed(snmp::MSG_TRAP_V1, |buf| {
+            push_varbinds(values, buf);
+            buf.push_timeticks(info.timestamp);
+            buf.push_integer(info.specific_trap);
+            buf.push_integer(info.generic_trap);
+            buf.push_ipaddress(agent_addr.octets());
+            buf.push_object_identifier_raw(info.enterprise.as_bytes());
+        });
+        buf.push_octet_string(community);
+        buf.push_integer(Version::V1 as i64);
+    });
+    Ok(())
+}
+
+/// Builds an SNMPv2 notification (Trap or InformRequest), the `sysUpTime.0` and `snmpTrapOID.0`
+/// varbinds are prepended to the values.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn build_notification(
+    version: Version,
+    community: &[u8],
+    ident: u8,
+    req_id: i32,
+    uptime: u32,
+    trap_oid: &Oid,
+    values: &[(&Oid, Value)],
+    buf: &mut Buf,
+    #[cfg(feature = "v3")] security: Option<&v3::Security>,
+) -> Result<()> {
+    if version == Version::V1 {
+        return Err(Error::UnsupportedVersion);
+    }
+    let (sys_uptime, snmp_trap_oid) = (snmp::SYS_UPTIME, snmp::SNMP_TRAP_OID);
+    let mut varbinds = Vec::with_capacity(values.len() + 2);
+    varbinds.push((&sys_uptime, Value::Timeticks(uptime)));
+    varbinds.push((&snmp_trap_oid, Value::ObjectIdentifier(trap_oid.clone())));
+    varbinds.extend(values.iter().map(|(oid, value)| (*oid, value.clone())));
+    build(
+        version,
+        community,
+        ident,
+        req_id,
+        &varbinds,
+        0,
+        0,
+        buf,
+        #[cfg(feature = "v3")]
+        security,
+    )
+}
+
+/// Builds the Response acknowledging a received InformRequest, with the same request ID and
+/// varbinds. SNMPv3 responses repeat the msgID of the inform and are secured with the context
+/// the inform has been received with.
+pub(crate) fn build_inform_response(
+    inform: &Pdu,
+    buf: &mut Buf,
+    #[cfg(feature = "v3")] security: Option<&v3::Security>,
+) -> Result<()> {
+    let (oids, values): (Vec<Oid>, Vec<Value>) = inform.varbinds.clone().unzip();
+    let values = oids.iter().zip(values).collect::<Vec<_>>();
+    let version = inform.version()?;
+    #[cfg(feature = "v3")]
+    if version == Version::V3 {
+        return v3::build(
+            snmp::MSG_RESPONSE,
+            inform.v3_msg_id,
+            inform.req_id,
+            &values,
+            0,
+            0,
+            buf,
+            security,
+        );
+    }
+    build(
+        version,
+        inform.community,
+        snmp::MSG_RESPONSE,
+        inform.req_id,
+        &values,
+        0,
+        0,
+        buf,
+        #[cfg(feature = "v3")]
+        security,
+    )
+}
+
+/// Reads the request ID (SNMPv1/v2c) or the msgID (SNMPv3) of an encoded message, without
+/// decoding the rest of it or touching the security context.
+pub(crate) fn peek_message_id(bytes: &[u8]) -> Option<i32> {
+    let seq = AsnReader::from_bytes(bytes)
+        .read_raw(asn1::TYPE_SEQUENCE)
+        .ok()?;
+    let mut rdr = AsnReader::from_bytes(seq);
+    let version = rdr.read_asn_integer().ok()?;
+    let id = if version == Version::V3 as i64 {
+        let global_data_seq = rdr.read_raw(asn1::TYPE_SEQUENCE).ok()?;
+        AsnReader::from_bytes(global_data_seq)
+            .read_asn_integer()
+            .ok()?
+    } else {
+        rdr.read_asn_octetstring().ok()?;
+        let ident = rdr.peek_byte().ok()?;
+        AsnReader::from_bytes(rdr.read_raw(ident).ok()?)
+            .read_asn_integer()
+            .ok()?
+    };
+    i32::try_from(id).ok()
+}
+
 #[derive(Debug, Clone)]
 pub struct Pdu<'a> {
     pub(crate) version: i64,
@@ -476,7 +630,7 @@ pub struct Pdu<'a> {
     pub v3_msg_id: i32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct V1TrapInfo<'a> {
     pub enterprise: Oid<'a>,
     pub agent_addr: IpAddr,
@@ -485,11 +639,83 @@ pub struct V1TrapInfo<'a> {
     pub timestamp: u32,
 }
 
+impl V1TrapInfo<'_> {
+    pub fn to_owned_info(&self) -> V1TrapInfo<'static> {
+        V1TrapInfo {
+            enterprise: self.enterprise.to_owned(),
+            agent_addr: self.agent_addr,
+            generic_trap: self.generic_trap,
+            specific_trap: self.specific_trap,
+            timestamp: self.timestamp,
+        }
+    }
+}
+
+/// Owned version of [`Pdu`], which does not borrow the session receive buffer.
+///
+/// The varbinds are decoded like when iterating [`Pdu::varbinds`]: the conversion stops at the
+/// first varbind which can not be decoded, and the following ones are dropped as well.
+/// [`Varbinds::try_iter`] tells whether the PDU has such a varbind.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedPdu {
+    pub(crate) version: i64,
+    pub community: Vec<u8>,
+    pub message_type: MessageType,
+    pub req_id: i32,
+    pub error_status: u32,
+    pub error_index: u32,
+    pub varbinds: Vec<(Oid<'static>, OwnedValue)>,
+    pub v1_trap_info: Option<V1TrapInfo<'static>>,
+    #[cfg(feature = "v3")]
+    pub v3_msg_id: i32,
+}
+
+impl OwnedPdu {
+    pub fn version(&self) -> Result<Version> {
+        self.version.try_into()
+    }
+
+    /// Borrows the varbinds in the form used for encoding, e.g. by `set` requests.
+    pub fn values(&self) -> Vec<(&Oid<'static>, Value<'_>)> {
+        self.varbinds
+            .iter()
+            .map(|(oid, value)| (oid, value.as_value()))
+            .collect()
+    }
+}
+
+impl<'a> From<&Pdu<'a>> for OwnedPdu {
+    fn from(pdu: &Pdu<'a>) -> Self {
+        pdu.to_owned_pdu()
+    }
+}
+
 impl<'a> Pdu<'a> {
     pub fn version(&self) -> Result<Version> {
         self.version.try_into()
     }
 
+    /// Copies the PDU into an [`OwnedPdu`].
+    pub fn to_owned_pdu(&self) -> OwnedPdu {
+        OwnedPdu {
+            version: self.version,
+            community: self.community.to_vec(),
+            message_type: self.message_type,
+            req_id: self.req_id,
+            error_status: self.error_status,
+            error_index: self.error_index,
+            varbinds: self
+                .varbinds
+                .clone()
+                .map(|(oid, value)| (oid.to_owned(), value.to_owned_value()))
+                .collect(),
+            v1_trap_info: self.v1_trap_info.as_ref().map(V1TrapInfo::to_owned_info),
+            #[cfg(feature = "v3")]
+            v3_msg_id: self.v3_msg_id,
+        }
+    }
+
     fn parse_trap_v1(mut rdr: AsnReader<'a>, version: i64, community: &'a [u8]) -> Result<Pdu<'a>> {
         if version != Version::V1 as i64 {
             return Err(Error::AsnWrongType);
@@ -539,6 +765,25 @@ impl<'a> Pdu<'a> {
         }
     }
 
+    /// Parses the message like [`Pdu::from_bytes_with_security`]. For SNMPv3 messages, the
+    /// security context is returned for reading as well, e.g. to secure a reply while the PDU
+    /// borrows the context.
+    #[cfg(feature = "v3")]
+    pub(crate) fn from_bytes_with_context(
+        bytes: &'a [u8],
+        security: Option<&'a mut v3::Security>,
+    ) -> Result<(Pdu<'a>, Option<&'a v3::Security>)> {
+        let mut rdr =
+            AsnReader::from_bytes(AsnReader::from_bytes(bytes).read_raw(asn1::TYPE_SEQUENCE)?);
+        let version = rdr.read_asn_integer()?;
+        match security {
+            Some(security) if version == Version::V3 as i64 => {
+                Self::parse_v3(bytes, rdr, security).map(|(pdu, security)| (pdu, Some(security)))
+            }
+            security => Self::from_bytes_inner(bytes, security).map(|pdu| (pdu, None)),
+        }
+    }
+
     pub(crate) fn from_bytes_inner(
         bytes: &'a [u8],
         #[cfg(feature = "v3")] security: Option<&'a mut v3::Security>,
@@ -557,7 +802,7 @@ impl<'a> Pdu<'a> {
             #[cfg(feature = "v3")]
             {
                 if let Some(security) = security {
-                    return Self::parse_v3(bytes, rdr, security);
+                    return Self::parse_v3(bytes, rdr, security).map(|(pdu, _)| pdu);
                 }
                 return Err(Error::AuthFailure(v3::AuthErrorKind::SecurityNotProvided));
             }
@@ -0,0 +1,114 @@
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tokio")]
+use std::{future::Future, pin::Pin};
+
+/// Resolves the destination of a synchronous session again.
+pub(crate) type Resolve = Box<dyn Fn() -> io::Result<Vec<SocketAddr>> + Send + Sync>;
+
+/// Resolves the destination of an asynchronous session again.
+#[cfg(feature = "tokio")]
+pub(crate) type AsyncResolve = Box<
+    dyn Fn() -> Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>> + Send + Sync,
+>;
+
+/// Resolved addresses of the agent, in the order they are tried.
+pub(crate) struct Peers<R> {
+    addrs: Vec<SocketAddr>,
+    current: usize,
+    resolve: Option<(R, Duration)>,
+    resolved_at: Instant,
+}
+
+impl<R> Peers<R> {
+    pub(crate) fn new(addrs: Vec<SocketAddr>, resolve: Option<(R, Duration)>) -> Self {
+        Self {
+            addrs: interleave(addrs),
+            current: 0,
+            resolve,
+            resolved_at: Instant::now(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    pub(crate) fn current(&self) -> usize {
+        self.current
+    }
+
+    pub(crate) fn addr(&self, index: usize) -> SocketAddr {
+        self.addrs[index]
+    }
+
+    /// Marks the address the session socket is connected to.
+    pub(crate) fn select(&mut self, index: usize) {
+        self.current = index;
+    }
+
+    /// Indices of the other addresses, in the order to fall back to them.
+    pub(crate) fn fallbacks(&self) -> impl Iterator<Item = usize> {
+        let (len, current) = (self.addrs.len(), self.current);
+        (1..len).map(move |offset| (current + offset) % len)
+    }
+
+    /// Returns the resolver when the resolve interval has elapsed.
+    pub(crate) fn resolve_due(&mut self) -> Option<&R> {
+        let (ref resolve, interval) = self.resolve.as_ref()?;
+        if self.resolved_at.elapsed() < *interval {
+            return None;
+        }
+        self.resolved_at = Instant::now();
+        Some(resolve)
+    }
+
+    /// Replaces the addresses with newly resolved ones. Returns `false` when the current address
+    /// is gone and the session must connect to one of the new addresses.
+    pub(crate) fn update(&mut self, addrs: Vec<SocketAddr>) -> bool {
+        if addrs.is_empty() {
+            // keep the previous addresses when the name does not resolve anymore
+            return true;
+        }
+        let addr = self.addrs[self.current];
+        self.addrs = interleave(addrs);
+        if let Some(current) = self.addrs.iter().position(|&a| a == addr) {
+            self.current = current;
+            true
+        } else {
+            self.current = 0;
+            false
+        }
+    }
+}
+
+/// Orders the addresses alternating between the address families, starting with the family of
+/// the first address.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v6 = first.is_ipv6();
+    let mut unique = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        if !unique.contains(&addr) {
+            unique.push(addr);
+        }
+    }
+    let (preferred, other): (Vec<_>, Vec<_>) =
+        unique.into_iter().partition(|a| a.is_ipv6() == first_v6);
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
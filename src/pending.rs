@@ -0,0 +1,109 @@
+//! Requests awaiting their responses, for the clients which receive the responses with a
+//! background task and dispatch them to the requests.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+use tokio::{sync::oneshot, task::JoinHandle};
+
+use crate::{Error, Result};
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Requests awaiting their responses, by key: the request ID (msgID for SNMPv3), with the agent
+/// address when the socket is shared by many agents.
+pub(crate) struct Pending<K> {
+    requests: Arc<Mutex<Requests<K>>>,
+}
+
+struct Requests<K> {
+    waiting: HashMap<K, oneshot::Sender<Vec<u8>>>,
+    /// The connection has been closed, no more responses can be received.
+    closed: bool,
+}
+
+impl<K> Clone for Pending<K> {
+    fn clone(&self) -> Self {
+        Self {
+            requests: self.requests.clone(),
+        }
+    }
+}
+
+impl<K> Default for Pending<K> {
+    fn default() -> Self {
+        Self {
+            requests: Arc::new(Mutex::new(Requests {
+                waiting: HashMap::new(),
+                closed: false,
+            })),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Copy> Pending<K> {
+    /// Registers a request, fails if the connection has been closed. The request is removed when
+    /// the guard is dropped, i.e. when it completes or is cancelled.
+    pub(crate) fn insert(
+        &self,
+        key: K,
+    ) -> Result<(PendingGuard<'_, K>, oneshot::Receiver<Vec<u8>>)> {
+        let (tx, rx) = oneshot::channel();
+        let mut requests = lock(&self.requests);
+        if requests.closed {
+            return Err(Error::Receive);
+        }
+        requests.waiting.insert(key, tx);
+        Ok((PendingGuard { pending: self, key }, rx))
+    }
+
+    /// Passes the response to the request awaiting it, if any.
+    pub(crate) fn dispatch(&self, key: K, response: &[u8]) {
+        if let Some(tx) = lock(&self.requests).waiting.remove(&key) {
+            let _ = tx.send(response.to_vec());
+        }
+    }
+
+    /// Fails the awaiting requests and the next ones.
+    pub(crate) fn close(&self) {
+        let mut requests = lock(&self.requests);
+        requests.closed = true;
+        requests.waiting.clear();
+    }
+}
+
+/// Removes an in-flight request from the pending map when it completes or is cancelled.
+pub(crate) struct PendingGuard<'a, K: Eq + Hash> {
+    pending: &'a Pending<K>,
+    key: K,
+}
+
+impl<K: Eq + Hash> Drop for PendingGuard<'_, K> {
+    fn drop(&mut self) {
+        lock(&self.pending.requests).waiting.remove(&self.key);
+    }
+}
+
+/// Background task receiving the responses, stopped when dropped with the last client handle.
+pub(crate) struct Receiver(JoinHandle<()>);
+
+impl Receiver {
+    pub(crate) fn spawn<F>(task: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        Self(tokio::spawn(task))
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
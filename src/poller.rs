@@ -0,0 +1,330 @@
+use std::{
+    io,
+    net::SocketAddr,
+    num::Wrapping,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    net::{ToSocketAddrs, UdpSocket},
+    time,
+};
+
+use crate::{
+    pdu::{self, Pdu},
+    pending::{lock, Pending, Receiver},
+    Error, MessageType, Oid, OwnedPdu, Result, RetryPolicy, Value, Version, BUFFER_SIZE,
+};
+
+#[cfg(feature = "v3")]
+use std::sync::MutexGuard;
+
+#[cfg(feature = "v3")]
+use crate::v3;
+
+/// Requests by agent address and request ID.
+type Requests = Pending<(SocketAddr, i32)>;
+
+/// IPv4 peers of a dual-stack socket are reported as IPv4-mapped IPv6 addresses.
+fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => v6
+            .ip()
+            .to_ipv4_mapped()
+            .map_or(addr, |ip| SocketAddr::new(ip.into(), v6.port())),
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+/// Agent polled by [`Poller`].
+#[derive(Clone)]
+pub struct Target {
+    addr: SocketAddr,
+    version: Version,
+    community: Vec<u8>,
+    retry: RetryPolicy,
+    #[cfg(feature = "v3")]
+    security: Option<Arc<Mutex<v3::Security>>>,
+}
+
+impl Target {
+    pub fn new_v1(addr: SocketAddr, community: &[u8]) -> Self {
+        Self::new(Version::V1, addr, community)
+    }
+
+    pub fn new_v2c(addr: SocketAddr, community: &[u8]) -> Self {
+        Self::new(Version::V2C, addr, community)
+    }
+
+    #[cfg(feature = "v3")]
+    pub fn new_v3(addr: SocketAddr, security: v3::Security) -> Self {
+        let mut target = Self::new(Version::V3, addr, &security.username);
+        target.security = Some(Arc::new(Mutex::new(security)));
+        target
+    }
+
+    fn new(version: Version, addr: SocketAddr, community: &[u8]) -> Self {
+        Self {
+            addr: canonical(addr),
+            version,
+            community: community.to_vec(),
+            retry: RetryPolicy::default(),
+            #[cfg(feature = "v3")]
+            security: None,
+        }
+    }
+
+    /// Sets the request retransmission policy. By default, requests have no timeout.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Locks the security context for building a request.
+    #[cfg(feature = "v3")]
+    fn prepare(&self) -> Option<MutexGuard<'_, v3::Security>> {
+        self.security.as_ref().map(|security| {
+            let mut security = lock(security);
+            security.correct_authoritative_engine_time();
+            security
+        })
+    }
+}
+
+/// Cloneable asynchronous SNMP client, which polls many agents over a single unconnected UDP
+/// socket.
+///
+/// A background task receives the responses and dispatches them to the awaiting requests by the
+/// agent address and the request ID (msgID for SNMPv3). Requests allocate only the space their
+/// encoded messages need, which allows to poll thousands of agents concurrently. The task is
+/// stopped when the last handle is dropped.
+#[derive(Clone)]
+pub struct Poller {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    socket: Arc<UdpSocket>,
+    req_id: Mutex<Wrapping<i32>>,
+    send_pdu: Mutex<Box<pdu::Buf>>,
+    pending: Requests,
+    /// Stopped with the last handle.
+    _receiver: Receiver,
+}
+
+impl Poller {
+    /// Binds the poller socket to the given local address (e.g. `0.0.0.0:0`). Must be called
+    /// within a tokio runtime, as the poller spawns a background receive task.
+    pub async fn bind<A>(local_addr: A, starting_req_id: i32) -> io::Result<Self>
+    where
+        A: ToSocketAddrs,
+    {
+        let socket = Arc::new(UdpSocket::bind(local_addr).await?);
+        let pending = Requests::default();
+        let receiver = Receiver::spawn(Self::receive(socket.clone(), pending.clone()));
+        Ok(Self {
+            inner: Arc::new(Inner {
+                socket,
+                req_id: Mutex::new(Wrapping(starting_req_id)),
+                send_pdu: Mutex::default(),
+                pending,
+                _receiver: receiver,
+            }),
+        })
+    }
+
+    async fn receive(socket: Arc<UdpSocket>, pending: Requests) {
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let Ok((len, peer)) = socket.recv_from(&mut buf).await else {
+                continue;
+            };
+            let Some(req_id) = pdu::peek_message_id(&buf[..len]) else {
+                continue;
+            };
+            pending.dispatch((canonical(peer), req_id), &buf[..len]);
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.socket.local_addr()
+    }
+
+    fn next_req_id(&self) -> i32 {
+        let mut req_id = lock(&self.inner.req_id);
+        let current = req_id.0;
+        *req_id += Wrapping(1);
+        current
+    }
+
+    /// Encodes a request with the shared send buffer and returns a copy of the message.
+    fn build<F>(&self, f: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce(&mut pdu::Buf) -> Result<()>,
+    {
+        let mut buf = lock(&self.inner.send_pdu);
+        f(&mut buf)?;
+        Ok(buf.to_vec())
+    }
+
+    /// Sends the request and waits for the response, retransmitting the request according to the
+    /// target retry policy.
+    async fn send_and_recv(&self, target: &Target, req_id: i32, msg: &[u8]) -> Result<Vec<u8>> {
+        let (_guard, mut rx) = self.inner.pending.insert((target.addr, req_id))?;
+        let retry = &target.retry;
+        for attempt in 0..=retry.retries {
+            if self.inner.socket.send_to(msg, target.addr).await.is_err() {
+                return Err(Error::Send);
+            }
+            let res = if let Some(timeout) = retry.attempt_timeout(attempt) {
+                match time::timeout(timeout, &mut rx).await {
+                    Ok(res) => res,
+                    Err(_) => continue,
+                }
+            } else {
+                (&mut rx).await
+            };
+            return res.map_err(|_| Error::Receive);
+        }
+        Err(Error::Timeout)
+    }
+
+    async fn request(&self, target: &Target, req_id: i32, msg: &[u8]) -> Result<OwnedPdu> {
+        let bytes = self.send_and_recv(target, req_id, msg).await?;
+        #[cfg(feature = "v3")]
+        let mut security = target.security.as_deref().map(lock);
+        let resp = Pdu::from_bytes_inner(
+            &bytes,
+            #[cfg(feature = "v3")]
+            security.as_deref_mut(),
+        )?;
+        resp.validate(MessageType::Response, req_id, &target.community)?;
+        Ok(resp.to_owned_pdu())
+    }
+
+    #[cfg(not(feature = "v3"))]
+    #[allow(clippy::unused_self, clippy::unused_async)]
+    pub async fn init(&self, _target: &Target) -> Result<()> {
+        Ok(())
+    }
+
+    /// Discovers the SNMPv3 engine of the target, does nothing for other versions.
+    #[cfg(feature = "v3")]
+    pub async fn init(&self, target: &Target) -> Result<()> {
+        let Some(ref security) = target.security else {
+            return Ok(());
+        };
+        if !lock(security).is_usm() {
+            return Ok(());
+        }
+        // send a request to get the engine id
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            v3::build_init(req_id, buf);
+            Ok(())
+        })?;
+        let bytes = self.send_and_recv(target, req_id, &msg).await?;
+        let mut security = lock(security);
+        security.reset_engine_id();
+        security.reset_engine_counters();
+        if let Err(e) = Pdu::from_bytes_inner(&bytes, Some(&mut security)) {
+            if e != Error::AuthUpdated {
+                return Err(e);
+            }
+        }
+        if security.need_init() {
+            return Err(Error::AuthFailure(v3::AuthErrorKind::NotAuthenticated));
+        }
+        Ok(())
+    }
+
+    pub async fn get(&self, target: &Target, oid: &Oid<'_>) -> Result<OwnedPdu> {
+        self.get_many(target, &[oid]).await
+    }
+
+    /// Sends a single GET request for all the given OIDs.
+    pub async fn get_many(&self, target: &Target, oids: &[&Oid<'_>]) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            pdu::build_get(
+                target.version,
+                &target.community,
+                req_id,
+                oids,
+                buf,
+                #[cfg(feature = "v3")]
+                target.prepare().as_deref(),
+            )
+        })?;
+        self.request(target, req_id, &msg).await
+    }
+
+    pub async fn getnext(&self, target: &Target, oid: &Oid<'_>) -> Result<OwnedPdu> {
+        self.getnext_many(target, &[oid]).await
+    }
+
+    /// Sends a single GETNEXT request for all the given OIDs.
+    pub async fn getnext_many(&self, target: &Target, oids: &[&Oid<'_>]) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            pdu::build_getnext(
+                target.version,
+                &target.community,
+                req_id,
+                oids,
+                buf,
+                #[cfg(feature = "v3")]
+                target.prepare().as_deref(),
+            )
+        })?;
+        self.request(target, req_id, &msg).await
+    }
+
+    pub async fn getbulk(
+        &self,
+        target: &Target,
+        oids: &[&Oid<'_>],
+        non_repeaters: u32,
+        max_repetitions: u32,
+    ) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            pdu::build_getbulk(
+                target.version,
+                &target.community,
+                req_id,
+                oids,
+                non_repeaters,
+                max_repetitions,
+                buf,
+                #[cfg(feature = "v3")]
+                target.prepare().as_deref(),
+            )
+        })?;
+        self.request(target, req_id, &msg).await
+    }
+
+    pub async fn set(&self, target: &Target, values: &[(&Oid<'_>, Value<'_>)]) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            pdu::build_set(
+                target.version,
+                &target.community,
+                req_id,
+                values,
+                buf,
+                #[cfg(feature = "v3")]
+                target.prepare().as_deref(),
+            )
+        })?;
+        self.request(target, req_id, &msg).await
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+/// Request retransmission policy of a session.
+///
+/// Each attempt re-sends the same encoded request and waits for a response up to the attempt
+/// timeout. When no timeout is set, a request waits for the response forever and is never
+/// retransmitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct RetryPolicy {
+    /// Time to wait for a response to the first attempt.
+    pub timeout: Option<Duration>,
+    /// Number of retransmissions after the first attempt.
+    pub retries: u32,
+    /// Attempt timeout growth.
+    pub backoff: Backoff,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: None,
+            retries: 0,
+            backoff: Backoff::Fixed,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(timeout: Duration, retries: u32) -> Self {
+        Self {
+            timeout: Some(timeout),
+            retries,
+            backoff: Backoff::Fixed,
+        }
+    }
+
+    pub fn with_backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Timeout of the given attempt, starting from zero.
+    pub(crate) fn attempt_timeout(&self, attempt: u32) -> Option<Duration> {
+        let timeout = self.timeout?;
+        Some(match self.backoff {
+            Backoff::Fixed => timeout,
+            Backoff::Exponential { max } => {
+                let factor = 1_u32.checked_shl(attempt).unwrap_or(u32::MAX);
+                timeout.saturating_mul(factor).min(max.max(timeout))
+            }
+        })
+    }
+}
+
+/// Attempt timeout growth of [`RetryPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// All attempts use the same timeout.
+    Fixed,
+    /// The timeout doubles with each retransmission, up to the given maximum.
+    Exponential { max: Duration },
+}
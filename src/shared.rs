@@ -0,0 +1,340 @@
+use std::{
+    io,
+    num::Wrapping,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    net::{TcpStream, UdpSocket},
+    sync, time,
+};
+
+use crate::{
+    pdu::{self, Pdu},
+    pending::{lock, Pending, Receiver},
+    socket::AsyncSocket,
+    tcp::{self, Framer},
+    Error, MessageType, Oid, OwnedPdu, Result, RetryPolicy, Value, Version, BUFFER_SIZE,
+};
+
+#[cfg(feature = "v3")]
+use std::sync::MutexGuard;
+
+#[cfg(feature = "v3")]
+use crate::v3;
+
+/// Cloneable asynchronous SNMP client handle, created with [`AsyncSession::into_shared`].
+///
+/// All clones share the session socket and may send requests concurrently. A background task
+/// receives the responses and dispatches them to the awaiting requests by their request ID (msgID
+/// for SNMPv3), responses to unknown or abandoned requests are dropped. The task is stopped when
+/// the last handle is dropped. Over TCP, requests fail once the connection is closed.
+///
+/// [`AsyncSession::into_shared`]: crate::AsyncSession::into_shared
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SharedSession {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    version: Version,
+    socket: SharedSocket,
+    community: Vec<u8>,
+    req_id: Mutex<Wrapping<i32>>,
+    retry: RetryPolicy,
+    pending: Pending<i32>,
+    send_pdu: Mutex<Box<pdu::Buf>>,
+    /// Stopped with the last handle.
+    _receiver: Receiver,
+    #[cfg(feature = "v3")]
+    security: Option<Mutex<v3::Security>>,
+}
+
+enum SharedSocket {
+    Udp(Arc<UdpSocket>),
+    /// The connection and the rest of a message partially written by a cancelled request.
+    Tcp(Arc<TcpStream>, sync::Mutex<Vec<u8>>),
+}
+
+impl SharedSocket {
+    async fn send(&self, msg: &[u8]) -> io::Result<()> {
+        match self {
+            SharedSocket::Udp(socket) => socket.send(msg).await.map(|_| ()),
+            SharedSocket::Tcp(stream, unsent) => {
+                let mut unsent = unsent.lock().await;
+                unsent.extend_from_slice(msg);
+                tcp::write_unsent(stream, &mut unsent).await
+            }
+        }
+    }
+}
+
+/// Passes the response to the request awaiting it.
+fn dispatch(pending: &Pending<i32>, response: &[u8]) {
+    if let Some(req_id) = pdu::peek_message_id(response) {
+        pending.dispatch(req_id, response);
+    }
+}
+
+impl SharedSession {
+    pub(crate) fn new(
+        version: Version,
+        socket: AsyncSocket,
+        community: Vec<u8>,
+        req_id: Wrapping<i32>,
+        retry: RetryPolicy,
+        #[cfg(feature = "v3")] security: Option<v3::Security>,
+    ) -> Self {
+        let pending = Pending::default();
+        let (socket, receiver) = match socket {
+            AsyncSocket::Udp(socket) => {
+                let socket = Arc::new(socket);
+                let receiver = Receiver::spawn(Self::receive(socket.clone(), pending.clone()));
+                (SharedSocket::Udp(socket), receiver)
+            }
+            AsyncSocket::Tcp(stream) => {
+                let (stream, unsent, framer) = stream.into_parts();
+                let stream = Arc::new(stream);
+                let receiver = Receiver::spawn(Self::receive_stream(
+                    stream.clone(),
+                    framer,
+                    pending.clone(),
+                ));
+                (
+                    SharedSocket::Tcp(stream, sync::Mutex::new(unsent)),
+                    receiver,
+                )
+            }
+        };
+        Self {
+            inner: Arc::new(Inner {
+                version,
+                socket,
+                community,
+                req_id: Mutex::new(req_id),
+                retry,
+                pending,
+                send_pdu: Mutex::default(),
+                _receiver: receiver,
+                #[cfg(feature = "v3")]
+                security: security.map(Mutex::new),
+            }),
+        }
+    }
+
+    async fn receive(socket: Arc<UdpSocket>, pending: Pending<i32>) {
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let Ok(len) = socket.recv(&mut buf).await else {
+                // e.g. ICMP port unreachable from the agent
+                continue;
+            };
+            dispatch(&pending, &buf[..len]);
+        }
+    }
+
+    async fn receive_stream(stream: Arc<TcpStream>, mut framer: Framer, pending: Pending<i32>) {
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            match tcp::read_message(&stream, &mut framer, &mut buf).await {
+                Ok(len) => dispatch(&pending, &buf[..len]),
+                Err(e) if tcp::is_skipped(&e) => {}
+                // the stream cannot be framed any further
+                Err(_) => break,
+            }
+        }
+        pending.close();
+    }
+
+    pub fn version(&self) -> Version {
+        self.inner.version
+    }
+
+    fn next_req_id(&self) -> i32 {
+        let mut req_id = lock(&self.inner.req_id);
+        let current = req_id.0;
+        *req_id += Wrapping(1);
+        current
+    }
+
+    /// Locks the security context for building a request.
+    #[cfg(feature = "v3")]
+    fn prepare(&self) -> Option<MutexGuard<'_, v3::Security>> {
+        self.inner.security.as_ref().map(|security| {
+            let mut security = lock(security);
+            security.correct_authoritative_engine_time();
+            security
+        })
+    }
+
+    /// Encodes a request with the shared send buffer and returns a copy of the message.
+    fn build<F>(&self, f: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce(&mut pdu::Buf) -> Result<()>,
+    {
+        let mut buf = lock(&self.inner.send_pdu);
+        f(&mut buf)?;
+        Ok(buf.to_vec())
+    }
+
+    /// Sends the request and waits for the response, retransmitting the request according to the
+    /// retry policy.
+    async fn send_and_recv(&self, req_id: i32, msg: &[u8]) -> Result<Vec<u8>> {
+        let (_guard, mut rx) = self.inner.pending.insert(req_id)?;
+        let retry = &self.inner.retry;
+        for attempt in 0..=retry.retries {
+            let stream = matches!(self.inner.socket, SharedSocket::Tcp(..));
+            if (attempt == 0 || !stream) && self.inner.socket.send(msg).await.is_err() {
+                return Err(Error::Send);
+            }
+            let res = if let Some(timeout) = retry.attempt_timeout(attempt) {
+                match time::timeout(timeout, &mut rx).await {
+                    Ok(res) => res,
+                    Err(_) => continue,
+                }
+            } else {
+                (&mut rx).await
+            };
+            return res.map_err(|_| Error::Receive);
+        }
+        Err(Error::Timeout)
+    }
+
+    async fn request(&self, req_id: i32, msg: &[u8]) -> Result<OwnedPdu> {
+        let bytes = self.send_and_recv(req_id, msg).await?;
+        #[cfg(feature = "v3")]
+        let mut security = self.inner.security.as_ref().map(lock);
+        let resp = Pdu::from_bytes_inner(
+            &bytes,
+            #[cfg(feature = "v3")]
+            security.as_deref_mut(),
+        )?;
+        resp.validate(MessageType::Response, req_id, &self.inner.community)?;
+        Ok(resp.to_owned_pdu())
+    }
+
+    #[cfg(not(feature = "v3"))]
+    #[allow(clippy::unused_self, clippy::unused_async)]
+    pub async fn init(&self) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "v3")]
+    pub async fn init(&self) -> Result<()> {
+        let Some(ref security) = self.inner.security else {
+            return Ok(());
+        };
+        if !lock(security).is_usm() {
+            return Ok(());
+        }
+        // send a request to get the engine id
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            v3::build_init(req_id, buf);
+            Ok(())
+        })?;
+        let bytes = self.send_and_recv(req_id, &msg).await?;
+        let mut security = lock(security);
+        security.reset_engine_id();
+        security.reset_engine_counters();
+        if let Err(e) = Pdu::from_bytes_inner(&bytes, Some(&mut security)) {
+            if e != Error::AuthUpdated {
+                return Err(e);
+            }
+        }
+        if security.need_init() {
+            return Err(Error::AuthFailure(v3::AuthErrorKind::NotAuthenticated));
+        }
+        Ok(())
+    }
+
+    pub async fn get(&self, oid: &Oid<'_>) -> Result<OwnedPdu> {
+        self.get_many(&[oid]).await
+    }
+
+    /// Sends a single GET request for all the given OIDs.
+    pub async fn get_many(&self, oids: &[&Oid<'_>]) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            #[cfg(feature = "v3")]
+            let security = self.prepare();
+            pdu::build_get(
+                self.inner.version,
+                &self.inner.community,
+                req_id,
+                oids,
+                buf,
+                #[cfg(feature = "v3")]
+                security.as_deref(),
+            )
+        })?;
+        self.request(req_id, &msg).await
+    }
+
+    pub async fn getnext(&self, oid: &Oid<'_>) -> Result<OwnedPdu> {
+        self.getnext_many(&[oid]).await
+    }
+
+    /// Sends a single GETNEXT request for all the given OIDs.
+    pub async fn getnext_many(&self, oids: &[&Oid<'_>]) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            #[cfg(feature = "v3")]
+            let security = self.prepare();
+            pdu::build_getnext(
+                self.inner.version,
+                &self.inner.community,
+                req_id,
+                oids,
+                buf,
+                #[cfg(feature = "v3")]
+                security.as_deref(),
+            )
+        })?;
+        self.request(req_id, &msg).await
+    }
+
+    pub async fn getbulk(
+        &self,
+        oids: &[&Oid<'_>],
+        non_repeaters: u32,
+        max_repetitions: u32,
+    ) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            #[cfg(feature = "v3")]
+            let security = self.prepare();
+            pdu::build_getbulk(
+                self.inner.version,
+                &self.inner.community,
+                req_id,
+                oids,
+                non_repeaters,
+                max_repetitions,
+                buf,
+                #[cfg(feature = "v3")]
+                security.as_deref(),
+            )
+        })?;
+        self.request(req_id, &msg).await
+    }
+
+    pub async fn set(&self, values: &[(&Oid<'_>, Value<'_>)]) -> Result<OwnedPdu> {
+        let req_id = self.next_req_id();
+        let msg = self.build(|buf| {
+            #[cfg(feature = "v3")]
+            let security = self.prepare();
+            pdu::build_set(
+                self.inner.version,
+                &self.inner.community,
+                req_id,
+                values,
+                buf,
+                #[cfg(feature = "v3")]
+                security.as_deref(),
+            )
+        })?;
+        self.request(req_id, &msg).await
+    }
+}
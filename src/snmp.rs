@@ -1,6 +1,8 @@
 #![allow(dead_code, clippy::identity_op, clippy::eq_op)]
 
-use crate::asn1;
+use std::borrow::Cow;
+
+use crate::{asn1, Oid};
 
 pub const MSG_GET: u8 = asn1::CLASS_CONTEXTSPECIFIC | asn1::CONSTRUCTED | 0;
 pub const MSG_GET_NEXT: u8 = asn1::CLASS_CONTEXTSPECIFIC | asn1::CONSTRUCTED | 1;
@@ -44,6 +46,20 @@ pub const ERRSTATUS_AUTHORIZATIONERROR: u32 = 16;
 pub const ERRSTATUS_NOTWRITABLE: u32 = 17;
 pub const ERRSTATUS_INCONSISTENTNAME: u32 = 18;
 
+/// Generic trap types of SNMPv1 traps (RFC 1157).
+pub const TRAP_COLD_START: i64 = 0;
+pub const TRAP_WARM_START: i64 = 1;
+pub const TRAP_LINK_DOWN: i64 = 2;
+pub const TRAP_LINK_UP: i64 = 3;
+pub const TRAP_AUTHENTICATION_FAILURE: i64 = 4;
+pub const TRAP_EGP_NEIGHBOR_LOSS: i64 = 5;
+pub const TRAP_ENTERPRISE_SPECIFIC: i64 = 6;
+
+/// sysUpTime.0, the first varbind of SNMPv2 notifications (RFC 3416).
+pub const SYS_UPTIME: Oid<'static> = Oid::new(Cow::Borrowed(&[0x2b, 6, 1, 2, 1, 1, 3, 0]));
+/// snmpTrapOID.0, the second varbind of SNMPv2 notifications, identifies the notification.
+pub const SNMP_TRAP_OID: Oid<'static> = Oid::new(Cow::Borrowed(&[0x2b, 6, 1, 6, 3, 1, 1, 4, 1, 0]));
+
 pub const V3_MSG_FLAGS_REPORTABLE: u8 = 0x04;
 pub const V3_MSG_FLAGS_PRIVACY: u8 = 0x02;
 pub const V3_MSG_FLAGS_AUTH: u8 = 0x01;
@@ -0,0 +1,194 @@
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol as IpProtocol, Socket as RawSocket, Type};
+
+use crate::tcp;
+
+/// Transport protocol of a session.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    #[default]
+    Udp,
+    /// SNMP over TCP (RFC 3430).
+    Tcp,
+}
+
+/// Session socket options, kept by the session to connect to the other agent addresses.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SocketConfig {
+    pub(crate) protocol: Protocol,
+    pub(crate) local_addr: Option<SocketAddr>,
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub(crate) bind_device: Option<Vec<u8>>,
+    pub(crate) recv_buffer_size: Option<usize>,
+}
+
+impl SocketConfig {
+    /// Creates a socket for the agent address, with the options applied and bound to the local
+    /// address. Stream sockets are bound only when the local address is set.
+    fn open(&self, peer: SocketAddr) -> io::Result<RawSocket> {
+        let local_addr = self.local_addr.unwrap_or_else(|| match peer {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        });
+        let socket = match self.protocol {
+            Protocol::Udp => RawSocket::new(
+                Domain::for_address(local_addr),
+                Type::DGRAM,
+                Some(IpProtocol::UDP),
+            )?,
+            Protocol::Tcp => RawSocket::new(
+                Domain::for_address(local_addr),
+                Type::STREAM,
+                Some(IpProtocol::TCP),
+            )?,
+        };
+        #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+        if let Some(ref interface) = self.bind_device {
+            socket.bind_device(Some(interface))?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if self.protocol == Protocol::Udp || self.local_addr.is_some() {
+            socket.bind(&local_addr.into())?;
+        }
+        if self.protocol == Protocol::Tcp {
+            socket.set_nodelay(true)?;
+        }
+        Ok(socket)
+    }
+
+    /// Creates a session socket, connected to the agent. The timeout limits connection
+    /// establishment and sending.
+    pub(crate) fn connect(
+        &self,
+        peer: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> io::Result<Socket> {
+        let socket = self.open(peer)?;
+        socket.set_write_timeout(timeout)?;
+        match self.protocol {
+            Protocol::Udp => {
+                socket.connect(&peer.into())?;
+                Ok(Socket::Udp(socket.into()))
+            }
+            Protocol::Tcp => {
+                if let Some(timeout) = timeout {
+                    socket.connect_timeout(&peer.into(), timeout)?;
+                } else {
+                    socket.connect(&peer.into())?;
+                }
+                Ok(Socket::Tcp(tcp::Stream::new(socket.into())))
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn connect_async(&self, peer: SocketAddr) -> io::Result<AsyncSocket> {
+        let socket = self.open(peer)?;
+        socket.set_nonblocking(true)?;
+        match self.protocol {
+            Protocol::Udp => {
+                socket.connect(&peer.into())?;
+                let socket = tokio::net::UdpSocket::from_std(socket.into())?;
+                Ok(AsyncSocket::Udp(socket))
+            }
+            Protocol::Tcp => {
+                let socket = tokio::net::TcpSocket::from_std_stream(socket.into());
+                let stream = socket.connect(peer).await?;
+                Ok(AsyncSocket::Tcp(tcp::AsyncStream::new(stream)))
+            }
+        }
+    }
+}
+
+/// Time left until the deadline, fails with [`io::ErrorKind::TimedOut`] when it has passed.
+pub(crate) fn remaining(deadline: Option<Instant>) -> io::Result<Option<Duration>> {
+    let Some(deadline) = deadline else {
+        return Ok(None);
+    };
+    match deadline.checked_duration_since(Instant::now()) {
+        Some(timeout) if !timeout.is_zero() => Ok(Some(timeout)),
+        _ => Err(io::ErrorKind::TimedOut.into()),
+    }
+}
+
+/// Socket of a synchronous session.
+pub(crate) enum Socket {
+    Udp(UdpSocket),
+    Tcp(tcp::Stream),
+}
+
+impl Socket {
+    /// Stream sockets deliver messages reliably, requests are not retransmitted over them.
+    pub(crate) fn is_stream(&self) -> bool {
+        matches!(self, Socket::Tcp(_))
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Socket::Udp(socket) => socket.local_addr(),
+            Socket::Tcp(stream) => stream.get_ref().local_addr(),
+        }
+    }
+
+    pub(crate) fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        match self {
+            Socket::Udp(socket) => socket.send(msg).map(|_| ()),
+            Socket::Tcp(stream) => stream.send(msg),
+        }
+    }
+
+    /// Receives the next message, waiting until the deadline at most.
+    pub(crate) fn recv(&mut self, out: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        match self {
+            Socket::Udp(socket) => {
+                socket.set_read_timeout(remaining(deadline)?)?;
+                socket.recv(out)
+            }
+            Socket::Tcp(stream) => stream.recv(out, deadline),
+        }
+    }
+}
+
+/// Socket of an asynchronous session.
+#[cfg(feature = "tokio")]
+pub(crate) enum AsyncSocket {
+    Udp(tokio::net::UdpSocket),
+    Tcp(tcp::AsyncStream),
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncSocket {
+    /// Stream sockets deliver messages reliably, requests are not retransmitted over them.
+    pub(crate) fn is_stream(&self) -> bool {
+        matches!(self, AsyncSocket::Tcp(_))
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            AsyncSocket::Udp(socket) => socket.local_addr(),
+            AsyncSocket::Tcp(stream) => stream.get_ref().local_addr(),
+        }
+    }
+
+    pub(crate) async fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        match self {
+            AsyncSocket::Udp(socket) => socket.send(msg).await.map(|_| ()),
+            AsyncSocket::Tcp(stream) => stream.send(msg).await,
+        }
+    }
+
+    /// Receives the next message. Cancellation-safe.
+    pub(crate) async fn recv(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        match self {
+            AsyncSocket::Udp(socket) => socket.recv(out).await,
+            AsyncSocket::Tcp(stream) => stream.recv(out).await,
+        }
+    }
+}
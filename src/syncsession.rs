@@ -1,26 +1,37 @@
 use std::{
     io,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    net::{SocketAddr, ToSocketAddrs},
     num::Wrapping,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    pdu::{self, Pdu},
-    Error, MessageType, Oid, Result, Value, Version, BUFFER_SIZE,
+    builder::SessionBuilder,
+    pdu::{self, Pdu, V1TrapInfo},
+    peers::Peers,
+    snmp,
+    table::{Table, TableState},
+    transport::{NetTransport, Transport},
+    walk::Walk,
+    Error, MessageType, Oid, Result, RetryPolicy, Value, Version,
 };
 
 #[cfg(feature = "v3")]
 use crate::v3;
 
 /// Synchronous SNMP client
-pub struct SyncSession {
+///
+/// Sends its requests over UDP or TCP by default, or over any other [`Transport`] given to
+/// [`SessionBuilder::build_sync_with`].
+pub struct SyncSession<T = NetTransport> {
     version: Version,
-    socket: UdpSocket,
+    transport: T,
     community: Vec<u8>,
     req_id: Wrapping<i32>,
+    retry: RetryPolicy,
+    max_repetitions: u32,
     send_pdu: pdu::Buf,
-    recv_buf: [u8; BUFFER_SIZE],
+    recv_buf: Vec<u8>,
     #[cfg(feature = "v3")]
     security: Option<v3::Security>,
 }
@@ -72,10 +83,13 @@ impl SyncSession {
     where
         SA: ToSocketAddrs,
     {
-        let mut session = Self::new(Version::V3, destination, &[], timeout, starting_req_id)?;
-        session.community = security.username.clone();
-        session.security = Some(security);
-        Ok(session)
+        SessionBuilder::v3(security)
+            .with_retry_policy(RetryPolicy {
+                timeout,
+                ..RetryPolicy::default()
+            })
+            .with_starting_req_id(starting_req_id)
+            .connect_sync(Peers::new(destination.to_socket_addrs()?.collect(), None))
     }
 
     fn new<SA>(
@@ -88,29 +102,40 @@ impl SyncSession {
     where
         SA: ToSocketAddrs,
     {
-        let socket = match destination.to_socket_addrs()?.next() {
-            Some(SocketAddr::V4(_)) => UdpSocket::bind((Ipv4Addr::new(0, 0, 0, 0), 0))?,
-            Some(SocketAddr::V6(_)) => UdpSocket::bind((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0), 0))?,
-            None => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "No address found",
-                ))
-            }
-        };
-        socket.set_read_timeout(timeout)?;
-        socket.set_write_timeout(timeout)?;
-        socket.connect(destination)?;
-        Ok(Self {
-            version,
-            socket,
-            community: community.to_vec(),
-            req_id: Wrapping(starting_req_id),
+        SessionBuilder::new(version, community)
+            .with_retry_policy(RetryPolicy {
+                timeout,
+                ..RetryPolicy::default()
+            })
+            .with_starting_req_id(starting_req_id)
+            .connect_sync(Peers::new(destination.to_socket_addrs()?.collect(), None))
+    }
+
+    /// Local address of the session socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+
+    /// Agent address the session currently sends its requests to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.transport.peer_addr()
+    }
+}
+
+impl<T: Transport> SyncSession<T> {
+    pub(crate) fn from_builder(builder: &SessionBuilder, transport: T) -> Self {
+        Self {
+            version: builder.version,
+            transport,
+            community: builder.community.clone(),
+            req_id: Wrapping(builder.starting_req_id),
+            retry: builder.retry,
+            max_repetitions: builder.max_repetitions,
             send_pdu: pdu::Buf::default(),
-            recv_buf: [0; BUFFER_SIZE],
+            recv_buf: vec![0; builder.max_message_size],
             #[cfg(feature = "v3")]
-            security: None,
-        })
+            security: builder.security.clone(),
+        }
     }
 
     #[cfg(feature = "v3")]
@@ -125,19 +150,97 @@ impl SyncSession {
         Ok(self)
     }
 
+    /// Sets the request retransmission policy. The session timeout, given to the constructor, is
+    /// replaced with the policy timeout.
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        self.retry
+    }
+
+    /// Sends the request and waits for the response with the same request ID (msgID for
+    /// SNMPv3), retransmitting the request according to the retry policy. Responses with other
+    /// IDs, e.g. late responses to previous requests, are dropped.
     fn send_and_recv<'a>(
-        socket: &UdpSocket,
+        transport: &mut T,
+        retry: &RetryPolicy,
+        req_id: i32,
         pdu: &pdu::Buf,
         out: &'a mut [u8],
     ) -> Result<&'a [u8]> {
-        if let Ok(_pdu_len) = socket.send(pdu) {
-            match socket.recv(out) {
-                Ok(len) => Ok(&out[..len]),
-                Err(_) => Err(Error::Receive),
+        for attempt in 0..=retry.retries {
+            if (attempt == 0 || !transport.is_reliable()) && transport.send(pdu).is_err() {
+                return Err(Error::Send);
+            }
+            let deadline = retry
+                .attempt_timeout(attempt)
+                .map(|timeout| Instant::now() + timeout);
+            loop {
+                let len = match transport.recv(out, deadline) {
+                    Ok(len) => len,
+                    Err(e) if is_timeout(&e) => break,
+                    Err(_) => return Err(Error::Receive),
+                };
+                if pdu::peek_message_id(&out[..len]).map_or(true, |id| id == req_id) {
+                    return Ok(&out[..len]);
+                }
             }
-        } else {
-            Err(Error::Send)
         }
+        Err(Error::Timeout)
+    }
+
+    /// Sends the request, and again after the transport reconnects when it can not be sent, the
+    /// agent is unreachable or does not respond. Returns the response length.
+    fn exchange(&mut self, req_id: i32) -> Result<usize> {
+        self.transport.begin();
+        loop {
+            match Self::send_and_recv(
+                &mut self.transport,
+                &self.retry,
+                req_id,
+                &self.send_pdu,
+                &mut self.recv_buf,
+            ) {
+                Ok(response) => return Ok(response.len()),
+                Err(e @ (Error::Send | Error::Receive | Error::Timeout)) => {
+                    if !self.transport.reconnect() {
+                        return Err(e);
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Sends the message without waiting for a response, and again after the transport
+    /// reconnects when it can not be sent.
+    fn send_unconfirmed(&mut self) -> Result<()> {
+        self.transport.begin();
+        while self.transport.send(&self.send_pdu).is_err() {
+            if !self.transport.reconnect() {
+                return Err(Error::Send);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
     }
 
     #[cfg(not(feature = "v3"))]
@@ -147,25 +250,27 @@ impl SyncSession {
 
     #[cfg(feature = "v3")]
     pub fn init(&mut self) -> Result<()> {
-        if let Some(ref mut security) = self.security {
-            security.reset_engine_id();
-            security.reset_engine_counters();
-            // send a request to get the engine id
-            let req_id = self.req_id.0;
-            v3::build_init(req_id, &mut self.send_pdu);
-            self.req_id += Wrapping(1);
-            if let Err(e) = Pdu::from_bytes_inner(
-                Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf)?,
-                Some(security),
-            ) {
-                if e != Error::AuthUpdated {
-                    return Err(e);
-                }
-            }
-            if security.need_init() {
-                return Err(Error::AuthFailure(v3::AuthErrorKind::NotAuthenticated));
+        if !self.security.as_ref().map_or(false, v3::Security::is_usm) {
+            return Ok(());
+        }
+        // send a request to get the engine id
+        let req_id = self.req_id.0;
+        v3::build_init(req_id, &mut self.send_pdu);
+        self.req_id += Wrapping(1);
+        let len = self.exchange(req_id)?;
+        let Some(ref mut security) = self.security else {
+            return Ok(());
+        };
+        security.reset_engine_id();
+        security.reset_engine_counters();
+        if let Err(e) = Pdu::from_bytes_inner(&self.recv_buf[..len], Some(security)) {
+            if e != Error::AuthUpdated {
+                return Err(e);
             }
         }
+        if security.need_init() {
+            return Err(Error::AuthFailure(v3::AuthErrorKind::NotAuthenticated));
+        }
         Ok(())
     }
 
@@ -181,45 +286,57 @@ impl SyncSession {
     }
 
     pub fn get(&mut self, oid: &Oid) -> Result<Pdu> {
+        self.get_many(&[oid])
+    }
+
+    /// Sends a single GET request for all the given OIDs.
+    pub fn get_many(&mut self, oids: &[&Oid]) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_get(
             self.version,
             self.community.as_slice(),
             req_id,
-            oid,
+            oids,
             &mut self.send_pdu,
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id)?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf)?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
-        self.req_id += Wrapping(1);
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
 
     pub fn getnext(&mut self, oid: &Oid) -> Result<Pdu> {
+        self.getnext_many(&[oid])
+    }
+
+    /// Sends a single GETNEXT request for all the given OIDs.
+    pub fn getnext_many(&mut self, oids: &[&Oid]) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_getnext(
             self.version,
             self.community.as_slice(),
             req_id,
-            oid,
+            oids,
             &mut self.send_pdu,
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id)?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf)?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
-        self.req_id += Wrapping(1);
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
@@ -232,6 +349,7 @@ impl SyncSession {
     ) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_getbulk(
             self.version,
             self.community.as_slice(),
@@ -243,12 +361,12 @@ impl SyncSession {
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id)?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf)?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
-        self.req_id += Wrapping(1);
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
@@ -256,6 +374,7 @@ impl SyncSession {
     pub fn set(&mut self, values: &[(&Oid, Value)]) -> Result<Pdu> {
         self.prepare();
         let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
         pdu::build_set(
             self.version,
             self.community.as_slice(),
@@ -265,13 +384,131 @@ impl SyncSession {
             #[cfg(feature = "v3")]
             self.security.as_ref(),
         )?;
+        let len = self.exchange(req_id)?;
         let resp = Pdu::from_bytes_inner(
-            Self::send_and_recv(&self.socket, &self.send_pdu, &mut self.recv_buf)?,
+            &self.recv_buf[..len],
             #[cfg(feature = "v3")]
             self.security.as_mut(),
         )?;
+        resp.validate(MessageType::Response, req_id, &self.community)?;
+        Ok(resp)
+    }
+
+    /// Sends an SNMPv1 trap, e.g. to a notification receiver on port 162. Traps are not
+    /// acknowledged, the method returns once the trap has been sent.
+    pub fn trap_v1(&mut self, info: &V1TrapInfo, values: &[(&Oid, Value)]) -> Result<()> {
+        if self.version != Version::V1 {
+            return Err(Error::UnsupportedVersion);
+        }
+        pdu::build_trap_v1(&self.community, info, values, &mut self.send_pdu)?;
+        self.send_unconfirmed()
+    }
+
+    /// Sends an SNMPv2c or SNMPv3 trap, e.g. to a notification receiver on port 162. The
+    /// `sysUpTime.0` (`uptime` in hundredths of a second) and `snmpTrapOID.0` varbinds are
+    /// prepended to the values. Traps are not acknowledged, the method returns once the trap has
+    /// been sent.
+    ///
+    /// The originator of SNMPv3 traps is their authoritative engine: the security parameters
+    /// hold the local engine ID, boots and time.
+    pub fn trap(&mut self, trap_oid: &Oid, uptime: u32, values: &[(&Oid, Value)]) -> Result<()> {
+        self.prepare();
+        let req_id = self.req_id.0;
         self.req_id += Wrapping(1);
+        pdu::build_notification(
+            self.version,
+            &self.community,
+            snmp::MSG_TRAP,
+            req_id,
+            uptime,
+            trap_oid,
+            values,
+            &mut self.send_pdu,
+            #[cfg(feature = "v3")]
+            self.security.as_ref(),
+        )?;
+        self.send_unconfirmed()
+    }
+
+    /// Sends an SNMPv2c or SNMPv3 inform request, e.g. to a notification receiver on port 162,
+    /// and waits for the receiver to acknowledge it. The varbinds are prepended like with
+    /// [`trap`](Self::trap).
+    ///
+    /// The inform is retransmitted according to the retry policy, e.g. with
+    /// [`Backoff::Exponential`](crate::Backoff::Exponential), until the Response arrives.
+    /// [`Error::Timeout`] reports that it has not been delivered. The receiver of SNMPv3 informs
+    /// is their authoritative engine, like an agent is for requests.
+    pub fn inform(&mut self, trap_oid: &Oid, uptime: u32, values: &[(&Oid, Value)]) -> Result<Pdu> {
+        self.prepare();
+        let req_id = self.req_id.0;
+        self.req_id += Wrapping(1);
+        pdu::build_notification(
+            self.version,
+            &self.community,
+            snmp::MSG_INFORM,
+            req_id,
+            uptime,
+            trap_oid,
+            values,
+            &mut self.send_pdu,
+            #[cfg(feature = "v3")]
+            self.security.as_ref(),
+        )?;
+        let len = self.exchange(req_id)?;
+        let resp = Pdu::from_bytes_inner(
+            &self.recv_buf[..len],
+            #[cfg(feature = "v3")]
+            self.security.as_mut(),
+        )?;
         resp.validate(MessageType::Response, req_id, &self.community)?;
         Ok(resp)
     }
+
+    /// Walks the subtree under the given OID, using GETBULK for SNMPv2c/v3 and GETNEXT for
+    /// SNMPv1.
+    ///
+    /// The walk stops at the end of the subtree or the MIB view. An agent which returns OIDs out
+    /// of order stops it with [`Error::OidNotIncreasing`].
+    pub fn walk(&mut self, oid: &Oid) -> Walk<'_, T> {
+        let max_repetitions = self.max_repetitions;
+        Walk::new(self, oid, max_repetitions)
+    }
+
+    /// Fetches the given columns of a conceptual table, `entry` is the table entry OID (e.g.
+    /// ifEntry `1.3.6.1.2.1.2.2.1`).
+    ///
+    /// The columns are requested in parallel with GETBULK for SNMPv2c/v3 and GETNEXT for SNMPv1,
+    /// the values are assembled into rows by their instance index.
+    pub fn table(&mut self, entry: &Oid, columns: &[u32]) -> Result<Table> {
+        let mut state = TableState::new(entry, columns)?;
+        while let Some(oids) = state.next_oids() {
+            let res = self.table_request(&mut state, &oids);
+            // the security context has been updated, the request is safe to repeat
+            #[cfg(feature = "v3")]
+            let res = if res == Err(Error::AuthUpdated) {
+                self.table_request(&mut state, &oids)
+            } else {
+                res
+            };
+            res?;
+        }
+        Ok(state.into_table())
+    }
+
+    fn table_request(&mut self, state: &mut TableState, oids: &[Oid]) -> Result<()> {
+        let oids = oids.iter().collect::<Vec<_>>();
+        let pdu = if self.version == Version::V1 {
+            self.getnext_many(&oids)?
+        } else {
+            self.getbulk(&oids, 0, self.max_repetitions)?
+        };
+        state.feed(&pdu)
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
 }
@@ -0,0 +1,167 @@
+use std::{cmp::Ordering, collections::BTreeMap};
+
+use crate::{
+    snmp, walk::oid_cmp, Error, MessageType, Oid, OwnedValue, Pdu, Result, Value, Varbinds,
+};
+
+/// Conceptual table, fetched with `table` methods of the sessions.
+///
+/// Rows are keyed by their instance index (the OID suffix after the column number) and sorted in
+/// the same order the agent returns them. Each row has a value slot for every requested column,
+/// columns which have no instance for the row (sparse tables) are set to `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table {
+    pub columns: Vec<u32>,
+    pub rows: BTreeMap<Vec<u64>, Vec<Option<OwnedValue>>>,
+}
+
+impl Table {
+    /// Returns a cell value by the row index and the column number.
+    pub fn value(&self, index: &[u64], column: u32) -> Option<&OwnedValue> {
+        let pos = self.columns.iter().position(|c| *c == column)?;
+        self.rows.get(index)?.get(pos)?.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+struct Column {
+    prefix: Oid<'static>,
+    prefix_len: usize,
+    cursor: Oid<'static>,
+    done: bool,
+}
+
+/// Table fetch progress, shared by the sync and async sessions.
+///
+/// All columns are requested in parallel: each GETNEXT/GETBULK request carries the last OID
+/// received for every column which has not reached its end yet.
+pub(crate) struct TableState {
+    columns: Vec<Column>,
+    active: Vec<usize>,
+    table: Table,
+}
+
+impl TableState {
+    pub(crate) fn new(entry: &Oid, columns: &[u32]) -> Result<Self> {
+        let entry_arcs = entry
+            .iter()
+            .ok_or(Error::ValueOutOfRange)?
+            .collect::<Vec<_>>();
+        let columns_state = columns
+            .iter()
+            .map(|column| {
+                let mut arcs = entry_arcs.clone();
+                arcs.push(u64::from(*column));
+                let prefix = Oid::from(&arcs).map_err(|_| Error::ValueOutOfRange)?;
+                Ok(Column {
+                    cursor: prefix.clone(),
+                    prefix,
+                    prefix_len: arcs.len(),
+                    done: false,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            columns: columns_state,
+            active: Vec::new(),
+            table: Table {
+                columns: columns.to_vec(),
+                rows: BTreeMap::new(),
+            },
+        })
+    }
+
+    /// OIDs for the next request, `None` when all columns are finished.
+    pub(crate) fn next_oids(&mut self) -> Option<Vec<Oid<'static>>> {
+        self.active = (0..self.columns.len())
+            .filter(|i| !self.columns[*i].done)
+            .collect();
+        if self.active.is_empty() {
+            return None;
+        }
+        Some(
+            self.active
+                .iter()
+                .map(|i| self.columns[*i].cursor.clone())
+                .collect(),
+        )
+    }
+
+    pub(crate) fn feed(&mut self, pdu: &Pdu) -> Result<()> {
+        if pdu.message_type != MessageType::Response {
+            return Err(Error::AsnWrongType);
+        }
+        if pdu.error_status == snmp::ERRSTATUS_NOSUCHNAME {
+            // SNMPv1 agents report the end of the MIB view for the whole request, finish the
+            // column which has caused it and repeat the request for the others
+            let failed = usize::try_from(pdu.error_index)?
+                .checked_sub(1)
+                .and_then(|i| self.active.get(i));
+            if let Some(column) = failed {
+                self.columns[*column].done = true;
+            } else {
+                self.finish();
+            }
+            return Ok(());
+        }
+        if pdu.error_status != snmp::ERRSTATUS_NOERROR {
+            return Err(Error::ErrorStatus {
+                status: pdu.error_status,
+                index: pdu.error_index,
+            });
+        }
+        self.feed_varbinds(&pdu.varbinds)
+    }
+
+    fn feed_varbinds(&mut self, varbinds: &Varbinds) -> Result<()> {
+        let mut received = false;
+        for (n, varbind) in varbinds.try_iter().enumerate() {
+            let (name, value) = varbind?;
+            received = true;
+            let pos = self.active[n % self.active.len()];
+            let column = &mut self.columns[pos];
+            if column.done {
+                continue;
+            }
+            if matches!(value, Value::EndOfMibView) || !name.starts_with(&column.prefix) {
+                column.done = true;
+                continue;
+            }
+            if oid_cmp(&name, &column.cursor) != Ordering::Greater {
+                return Err(Error::OidNotIncreasing);
+            }
+            let index = name
+                .iter()
+                .ok_or(Error::ValueOutOfRange)?
+                .skip(column.prefix_len)
+                .collect::<Vec<_>>();
+            column.cursor = name.to_owned();
+            let columns = self.columns.len();
+            self.table
+                .rows
+                .entry(index)
+                .or_insert_with(|| vec![None; columns])[pos] = Some(value.to_owned_value());
+        }
+        if !received {
+            self.finish();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        for column in &mut self.columns {
+            column.done = true;
+        }
+    }
+
+    pub(crate) fn into_table(self) -> Table {
+        self.table
+    }
+}
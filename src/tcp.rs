@@ -0,0 +1,235 @@
+//! SNMP over TCP (RFC 3430): messages are sent back to back over the connection, each one
+//! delimited by its own BER length.
+
+use std::{
+    error, fmt,
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use crate::{asn1, socket::remaining};
+
+/// Size of the chunks read from the connection.
+pub(crate) const READ_CHUNK: usize = 4096;
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// A message larger than the receive buffer, which has been skipped. Unlike the other framing
+/// errors, the next messages of the connection can still be received.
+#[derive(Debug)]
+struct MessageTooLarge;
+
+impl fmt::Display for MessageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Message too large")
+    }
+}
+
+impl error::Error for MessageTooLarge {}
+
+/// Whether the error is about a skipped message, the connection can be read further.
+#[cfg(feature = "tokio")]
+pub(crate) fn is_skipped(e: &io::Error) -> bool {
+    e.get_ref().map_or(false, |e| e.is::<MessageTooLarge>())
+}
+
+/// Length of the BER-encoded message at the start of the buffer, `None` when the buffer does not
+/// contain the complete message header yet.
+fn message_len(buf: &[u8]) -> io::Result<Option<usize>> {
+    let Some(&tag) = buf.first() else {
+        return Ok(None);
+    };
+    if tag != asn1::TYPE_SEQUENCE {
+        return Err(invalid_data("Stream is not a sequence of SNMP messages"));
+    }
+    let Some(&first) = buf.get(1) else {
+        return Ok(None);
+    };
+    if first < 0x80 {
+        return Ok(Some(2 + usize::from(first)));
+    }
+    // definite long form, the indefinite form is not allowed
+    let len_len = usize::from(first & 0x7f);
+    if len_len == 0 || len_len > 4 {
+        return Err(invalid_data("Invalid message length"));
+    }
+    let Some(len_bytes) = buf.get(2..2 + len_len) else {
+        return Ok(None);
+    };
+    let len = len_bytes
+        .iter()
+        .fold(0, |len, &b| len << 8 | usize::from(b));
+    // 4 length bytes may overflow on 32-bit targets
+    (2 + len_len)
+        .checked_add(len)
+        .map(Some)
+        .ok_or_else(|| invalid_data("Invalid message length"))
+}
+
+/// Reassembles messages from the bytes received over a connection.
+#[derive(Default)]
+pub(crate) struct Framer {
+    pending: Vec<u8>,
+    discard: usize,
+}
+
+impl Framer {
+    pub(crate) fn push(&mut self, data: &[u8]) {
+        let skipped = self.discard.min(data.len());
+        self.discard -= skipped;
+        self.pending.extend_from_slice(&data[skipped..]);
+    }
+
+    /// Moves the next complete message into `out`, returns its length. Messages larger than `out`
+    /// are skipped with an error.
+    pub(crate) fn next_message(&mut self, out: &mut [u8]) -> io::Result<Option<usize>> {
+        let Some(len) = message_len(&self.pending)? else {
+            return Ok(None);
+        };
+        if len > out.len() {
+            let skipped = len.min(self.pending.len());
+            self.pending.drain(..skipped);
+            self.discard = len - skipped;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, MessageTooLarge));
+        }
+        if self.pending.len() < len {
+            return Ok(None);
+        }
+        out[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(Some(len))
+    }
+}
+
+/// Blocking connection the messages are framed over, plain TCP or secured by TLS.
+pub(crate) trait Connection: Read + Write {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Synchronous SNMP over TCP connection.
+pub(crate) struct Stream<S = TcpStream> {
+    stream: S,
+    framer: Framer,
+}
+
+impl<S: Connection> Stream<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self {
+            stream,
+            framer: Framer::default(),
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub(crate) fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.stream.write_all(msg)
+    }
+
+    /// Receives the next message, waiting until the deadline at most.
+    pub(crate) fn recv(&mut self, out: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        let mut chunk = [0; READ_CHUNK];
+        loop {
+            if let Some(len) = self.framer.next_message(out)? {
+                return Ok(len);
+            }
+            self.stream.set_read_timeout(remaining(deadline)?)?;
+            let len = self.stream.read(&mut chunk)?;
+            if len == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.framer.push(&chunk[..len]);
+        }
+    }
+}
+
+/// Asynchronous SNMP over TCP connection.
+///
+/// Sending and receiving are cancellation-safe: a message partially written by a cancelled send
+/// is completed by the next one, and partially received messages are kept.
+#[cfg(feature = "tokio")]
+pub(crate) struct AsyncStream {
+    stream: tokio::net::TcpStream,
+    unsent: Vec<u8>,
+    framer: Framer,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncStream {
+    pub(crate) fn new(stream: tokio::net::TcpStream) -> Self {
+        Self {
+            stream,
+            unsent: Vec::new(),
+            framer: Framer::default(),
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &tokio::net::TcpStream {
+        &self.stream
+    }
+
+    pub(crate) fn into_parts(self) -> (tokio::net::TcpStream, Vec<u8>, Framer) {
+        (self.stream, self.unsent, self.framer)
+    }
+
+    pub(crate) async fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.unsent.extend_from_slice(msg);
+        write_unsent(&self.stream, &mut self.unsent).await
+    }
+
+    pub(crate) async fn recv(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        read_message(&self.stream, &mut self.framer, out).await
+    }
+}
+
+/// Writes the unsent bytes to the connection, removing them as they are written.
+#[cfg(feature = "tokio")]
+pub(crate) async fn write_unsent(
+    stream: &tokio::net::TcpStream,
+    unsent: &mut Vec<u8>,
+) -> io::Result<()> {
+    while !unsent.is_empty() {
+        stream.writable().await?;
+        match stream.try_write(unsent) {
+            Ok(len) => {
+                unsent.drain(..len);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Receives the next message from the connection.
+#[cfg(feature = "tokio")]
+pub(crate) async fn read_message(
+    stream: &tokio::net::TcpStream,
+    framer: &mut Framer,
+    out: &mut [u8],
+) -> io::Result<usize> {
+    loop {
+        if let Some(len) = framer.next_message(out)? {
+            return Ok(len);
+        }
+        stream.readable().await?;
+        let mut chunk = [0; READ_CHUNK];
+        match stream.try_read(&mut chunk) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(len) => framer.push(&chunk[..len]),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -1,4 +1,11 @@
-use crate::{MessageType, Pdu, Value};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, UdpSocket},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{MessageType, OwnedValue, Pdu, SyncSession, Value, Varbinds};
 
 use super::{pdu, snmp, Oid};
 use super::{AsnReader, Error, Version};
@@ -10,7 +17,7 @@ fn build_getnext_pdu() {
         Version::V2C,
         b"tyS0n43d",
         1_251_699_618,
-        &Oid::from(&[1, 3, 6, 1, 2, 1, 1, 1, 0]).unwrap(),
+        &[&Oid::from(&[1, 3, 6, 1, 2, 1, 1, 1, 0]).unwrap()],
         &mut pdu,
         #[cfg(feature = "v3")]
         None,
@@ -29,6 +36,33 @@ fn build_getnext_pdu() {
     assert_eq!(&pdu[..], &expected[..]);
 }
 
+#[test]
+fn build_get_many_pdu() {
+    let sys_descr = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 1, 0]).unwrap();
+    let sys_uptime = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 3, 0]).unwrap();
+    let sys_name = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 5, 0]).unwrap();
+    let mut buf = pdu::Buf::default();
+    pdu::build_get(
+        Version::V2C,
+        b"public",
+        42,
+        &[&sys_descr, &sys_uptime, &sys_name],
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    let pdu = Pdu::from_bytes(&buf).unwrap();
+    assert_eq!(pdu.message_type, MessageType::GetRequest);
+    assert_eq!(pdu.req_id, 42);
+    let varbinds = pdu.varbinds.collect::<Vec<_>>();
+    assert_eq!(varbinds.len(), 3);
+    for ((name, value), expected) in varbinds.iter().zip([&sys_descr, &sys_uptime, &sys_name]) {
+        assert_eq!(name, expected);
+        assert!(matches!(value, Value::Null));
+    }
+}
+
 #[test]
 fn build_getbulk_pdu() {
     let mut pdu = pdu::Buf::default();
@@ -78,6 +112,349 @@ fn build_reply_pdu() {
     assert_eq!(pdu.error_index, 1);
 }
 
+#[test]
+fn parse_exception_values() {
+    let sys_descr = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 1, 0]).unwrap();
+    let sys_object_id = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 2, 0]).unwrap();
+    let sys_uptime = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 3, 0]).unwrap();
+    let sys_contact = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 4, 0]).unwrap();
+    let mut buf = pdu::Buf::default();
+    pdu::build(
+        Version::V2C,
+        b"tyS0n43d",
+        snmp::MSG_RESPONSE,
+        1_251_699_618,
+        &[
+            (&sys_descr, Value::NoSuchObject),
+            (&sys_object_id, Value::NoSuchInstance),
+            (&sys_uptime, Value::EndOfMibView),
+            (&sys_contact, Value::Integer(42)),
+        ],
+        0,
+        0,
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    let pdu = Pdu::from_bytes(&buf).unwrap();
+    let varbinds = pdu.varbinds.collect::<Vec<_>>();
+    assert_eq!(varbinds.len(), 4);
+    assert_eq!(varbinds[0].0, sys_descr);
+    assert!(matches!(varbinds[0].1, Value::NoSuchObject));
+    assert_eq!(varbinds[1].0, sys_object_id);
+    assert!(matches!(varbinds[1].1, Value::NoSuchInstance));
+    assert_eq!(varbinds[2].0, sys_uptime);
+    assert!(matches!(varbinds[2].1, Value::EndOfMibView));
+    assert_eq!(varbinds[3].0, sys_contact);
+    assert!(matches!(varbinds[3].1, Value::Integer(42)));
+}
+
+#[test]
+fn asn_read_exception_with_payload() {
+    let bytes = [snmp::SNMP_NOSUCHOBJECT, 0x01, 0x00];
+    let mut reader = AsnReader::from_bytes(&bytes[..]);
+    assert_eq!(reader.read_snmp_nosuchobject(), Err(Error::AsnInvalidLen));
+}
+
+#[test]
+fn varbinds_try_iter_reports_error() {
+    let bytes = [
+        0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05,
+        0x00, // ok
+        0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x02, 0x00, 0x1f,
+        0x00, // bad
+        0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, 0x05,
+        0x00, // ok
+    ];
+    let varbinds = Varbinds::from_bytes(&bytes);
+    assert_eq!(varbinds.clone().count(), 1);
+    let mut iter = varbinds.try_iter();
+    let (name, value) = iter.next().unwrap().unwrap();
+    assert_eq!(name, Oid::from(&[1, 3, 6, 1, 2, 1, 1, 1, 0]).unwrap());
+    assert!(matches!(value, Value::Null));
+    assert_eq!(
+        iter.next().unwrap().unwrap_err(),
+        Error::VarbindDecode {
+            offset: 14,
+            error: Box::new(Error::AsnUnsupportedType)
+        }
+    );
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn varbinds_try_iter_truncated() {
+    let bytes = [
+        0x30, 0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00, 0x30,
+        0x0c, 0x06, 0x08, 0x2b, 0x06,
+    ];
+    let results = Varbinds::from_bytes(&bytes).try_iter().collect::<Vec<_>>();
+    assert_eq!(results.len(), 2);
+    assert!(results[0].is_ok());
+    assert_eq!(
+        results[1].as_ref().unwrap_err(),
+        &Error::VarbindDecode {
+            offset: 14,
+            error: Box::new(Error::AsnInvalidLen)
+        }
+    );
+}
+
+fn encode_value(value: Value) -> Vec<u8> {
+    let mut buf = pdu::Buf::default();
+    match value {
+        Value::Counter32(n) => buf.push_counter32(n),
+        Value::Unsigned32(n) => buf.push_unsigned32(n),
+        Value::Timeticks(n) => buf.push_timeticks(n),
+        Value::Counter64(n) => buf.push_counter64(n),
+        _ => unreachable!(),
+    }
+    buf.to_vec()
+}
+
+#[test]
+fn unsigned_encoding() {
+    assert_eq!(encode_value(Value::Counter32(0)), [0x41, 0x01, 0x00]);
+    assert_eq!(encode_value(Value::Counter32(127)), [0x41, 0x01, 0x7f]);
+    assert_eq!(
+        encode_value(Value::Counter32(128)),
+        [0x41, 0x02, 0x00, 0x80]
+    );
+    assert_eq!(
+        encode_value(Value::Unsigned32(u32::MAX)),
+        [0x42, 0x05, 0x00, 0xff, 0xff, 0xff, 0xff]
+    );
+    assert_eq!(
+        encode_value(Value::Timeticks(0x8000_0000)),
+        [0x43, 0x05, 0x00, 0x80, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(
+        encode_value(Value::Counter64(i64::MAX as u64)),
+        [0x46, 0x08, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+    );
+    assert_eq!(
+        encode_value(Value::Counter64(1 << 63)),
+        [0x46, 0x09, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+    );
+    assert_eq!(
+        encode_value(Value::Counter64(u64::MAX)),
+        [0x46, 0x09, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+    );
+}
+
+#[test]
+fn unsigned_round_trip() {
+    for n in [
+        0,
+        1,
+        127,
+        128,
+        255,
+        256,
+        0x7fff_ffff,
+        0x8000_0000,
+        u32::MAX - 1,
+        u32::MAX,
+    ] {
+        let bytes = encode_value(Value::Counter32(n));
+        assert_eq!(AsnReader::from_bytes(&bytes).read_snmp_counter32(), Ok(n));
+        let bytes = encode_value(Value::Unsigned32(n));
+        assert_eq!(AsnReader::from_bytes(&bytes).read_snmp_unsigned32(), Ok(n));
+        let bytes = encode_value(Value::Timeticks(n));
+        assert_eq!(AsnReader::from_bytes(&bytes).read_snmp_timeticks(), Ok(n));
+    }
+    for n in [
+        0,
+        u64::from(u32::MAX),
+        u64::from(u32::MAX) + 1,
+        i64::MAX as u64,
+        1 << 63,
+        u64::MAX - 1,
+        u64::MAX,
+    ] {
+        let bytes = encode_value(Value::Counter64(n));
+        assert_eq!(AsnReader::from_bytes(&bytes).read_snmp_counter64(), Ok(n));
+    }
+}
+
+#[test]
+fn unsigned_decoding() {
+    // agents which do not prepend a zero octet
+    let bytes = [0x41, 0x04, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(
+        AsnReader::from_bytes(&bytes).read_snmp_counter32(),
+        Ok(u32::MAX)
+    );
+    let bytes = [0x46, 0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(
+        AsnReader::from_bytes(&bytes).read_snmp_counter64(),
+        Ok(u64::MAX)
+    );
+    // out of range
+    let bytes = [0x41, 0x05, 0x01, 0x00, 0x00, 0x00, 0x00];
+    assert_eq!(
+        AsnReader::from_bytes(&bytes).read_snmp_counter32(),
+        Err(Error::AsnIntOverflow)
+    );
+    let bytes = [
+        0x46, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    assert_eq!(
+        AsnReader::from_bytes(&bytes).read_snmp_counter64(),
+        Err(Error::AsnIntOverflow)
+    );
+    let bytes = [0x46, 0x00];
+    assert_eq!(
+        AsnReader::from_bytes(&bytes).read_snmp_counter64(),
+        Err(Error::AsnInvalidLen)
+    );
+}
+
+#[test]
+fn parse_counter64_varbind() {
+    let oid = Oid::from(&[1, 3, 6, 1, 2, 1, 31, 1, 1, 1, 6, 1]).unwrap();
+    let mut buf = pdu::Buf::default();
+    pdu::build(
+        Version::V2C,
+        b"public",
+        snmp::MSG_RESPONSE,
+        1,
+        &[(&oid, Value::Counter64(u64::MAX))],
+        0,
+        0,
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    let mut pdu = Pdu::from_bytes(&buf).unwrap();
+    let (name, value) = pdu.varbinds.next().unwrap();
+    assert_eq!(name, oid);
+    assert!(matches!(value, Value::Counter64(u64::MAX)));
+}
+
+#[test]
+fn owned_pdu_round_trip() {
+    fn assert_send_static<T: Send + 'static>(_: &T) {}
+
+    let sys_descr = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 1, 0]).unwrap();
+    let sys_object_id = Oid::from(&[1, 3, 6, 1, 2, 1, 1, 2, 0]).unwrap();
+    let if_hc_in_octets = Oid::from(&[1, 3, 6, 1, 2, 1, 31, 1, 1, 1, 6, 1]).unwrap();
+    let values = [
+        (&sys_descr, Value::OctetString(b"RoboPLC")),
+        (
+            &sys_object_id,
+            Value::ObjectIdentifier(Oid::from(&[1, 3, 6, 1, 4, 1, 8072]).unwrap()),
+        ),
+        (&if_hc_in_octets, Value::Counter64(u64::MAX)),
+    ];
+    let mut buf = pdu::Buf::default();
+    pdu::build(
+        Version::V2C,
+        b"public",
+        snmp::MSG_RESPONSE,
+        7,
+        &values,
+        0,
+        0,
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    let encoded = buf.to_vec();
+    let owned = Pdu::from_bytes(&encoded).unwrap().to_owned_pdu();
+    drop(encoded);
+    assert_send_static(&owned);
+    assert_eq!(owned.version().unwrap(), Version::V2C);
+    assert_eq!(owned.community, b"public");
+    assert_eq!(owned.message_type, MessageType::Response);
+    assert_eq!(owned.req_id, 7);
+    assert_eq!(
+        owned.varbinds,
+        [
+            (
+                sys_descr.clone(),
+                OwnedValue::OctetString(b"RoboPLC".to_vec())
+            ),
+            (
+                sys_object_id.clone(),
+                OwnedValue::ObjectIdentifier(Oid::from(&[1, 3, 6, 1, 4, 1, 8072]).unwrap())
+            ),
+            (if_hc_in_octets.clone(), OwnedValue::Counter64(u64::MAX)),
+        ]
+    );
+    assert_eq!(owned.clone(), owned);
+
+    let mut buf2 = pdu::Buf::default();
+    pdu::build(
+        Version::V2C,
+        &owned.community,
+        snmp::MSG_RESPONSE,
+        owned.req_id,
+        &owned.values(),
+        0,
+        0,
+        &mut buf2,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    assert_eq!(&buf2[..], &buf[..]);
+}
+
+#[test]
+fn owned_pdu_broken_varbind() {
+    let mut buf = pdu::Buf::default();
+    pdu::build(
+        Version::V2C,
+        b"public",
+        snmp::MSG_RESPONSE,
+        1,
+        &[],
+        0,
+        0,
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    let mut pdu = Pdu::from_bytes(&buf).unwrap();
+    // the second varbind has no value
+    let varbinds = [
+        0x30, 0x08, 0x06, 0x03, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x05, // 1.3.6.1 = 5
+        0x30, 0x05, 0x06, 0x03, 0x2b, 0x06, 0x01, // 1.3.6.1
+        0x30, 0x08, 0x06, 0x03, 0x2b, 0x06, 0x02, 0x02, 0x01, 0x07, // 1.3.6.2 = 7
+    ];
+    pdu.varbinds = Varbinds::from_bytes(&varbinds);
+    let owned = pdu.to_owned_pdu();
+    assert_eq!(
+        owned.varbinds,
+        [(Oid::from(&[1, 3, 6, 1]).unwrap(), OwnedValue::Integer(5))]
+    );
+    let mut results = pdu.varbinds.try_iter();
+    assert!(results.next().unwrap().is_ok());
+    assert!(matches!(
+        results.next().unwrap(),
+        Err(Error::VarbindDecode { offset: 10, .. })
+    ));
+    assert!(results.next().is_none());
+}
+
+#[test]
+fn owned_value_constructed() {
+    let bytes = [0x30, 0x03, 0x02, 0x01, 0x05];
+    let value = AsnReader::from_bytes(&bytes).read_value().unwrap();
+    let owned = value.to_owned_value();
+    assert_eq!(owned, OwnedValue::Sequence(vec![0x02, 0x01, 0x05]));
+    let Value::Sequence(mut rdr) = owned.as_value() else {
+        panic!("sequence expected");
+    };
+    assert_eq!(rdr.read_asn_integer(), Ok(5));
+    assert_eq!(format!("{:?}", owned), format!("{:?}", owned.as_value()));
+}
+
 #[test]
 fn asn_read_byte() {
     let bytes = [1, 2, 3, 4];
@@ -133,6 +510,1807 @@ fn asn_parse_getnext_pdu() {
         .unwrap();
 }
 
+fn oid(arcs: &[u64]) -> Oid<'static> {
+    Oid::from(arcs).unwrap()
+}
+
+struct Reply {
+    error_status: u32,
+    error_index: u32,
+    varbinds: Vec<(Oid<'static>, OwnedValue)>,
+}
+
+impl Reply {
+    fn ok(varbinds: Vec<(Oid<'static>, OwnedValue)>) -> Self {
+        Self {
+            error_status: 0,
+            error_index: 0,
+            varbinds,
+        }
+    }
+}
+
+/// Starts a local UDP agent which replies to each request with the handler result. The handler
+/// may return `None` to drop the request.
+fn spawn_agent<F>(mut handler: F) -> SocketAddr
+where
+    F: FnMut(&Pdu) -> Option<Reply> + Send + 'static,
+{
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 65535];
+        let mut send_buf = pdu::Buf::default();
+        while let Ok((len, peer)) = socket.recv_from(&mut buf) {
+            let Ok(request) = Pdu::from_bytes(&buf[..len]) else {
+                continue;
+            };
+            let Some(reply) = handler(&request) else {
+                continue;
+            };
+            encode_reply(&request, &reply, &mut send_buf);
+            socket.send_to(&send_buf, peer).unwrap();
+        }
+    });
+    addr
+}
+
+fn encode_reply(request: &Pdu, reply: &Reply, buf: &mut pdu::Buf) {
+    let values = reply
+        .varbinds
+        .iter()
+        .map(|(name, value)| (name, value.as_value()))
+        .collect::<Vec<_>>();
+    pdu::build(
+        request.version().unwrap(),
+        request.community,
+        snmp::MSG_RESPONSE,
+        request.req_id,
+        &values,
+        reply.error_status,
+        reply.error_index,
+        buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+}
+
+/// Reads an RFC 3430 framed message, `None` at the end of the stream.
+fn read_framed(stream: &mut impl Read) -> Option<Vec<u8>> {
+    let mut msg = vec![0; 2];
+    stream.read_exact(&mut msg).ok()?;
+    let len = if msg[1] < 0x80 {
+        usize::from(msg[1])
+    } else {
+        let mut len_bytes = vec![0; usize::from(msg[1] & 0x7f)];
+        stream.read_exact(&mut len_bytes).unwrap();
+        msg.extend_from_slice(&len_bytes);
+        len_bytes
+            .iter()
+            .fold(0, |len, &b| len << 8 | usize::from(b))
+    };
+    let header = msg.len();
+    msg.resize(header + len, 0);
+    stream.read_exact(&mut msg[header..]).unwrap();
+    Some(msg)
+}
+
+/// Starts a local SNMP over TCP agent, like [`spawn_agent`]. The agent closes each connection
+/// after the given number of requests and writes the responses in two parts.
+fn spawn_tcp_agent<F>(mut handler: F, requests_per_connection: usize) -> SocketAddr
+where
+    F: FnMut(&Pdu) -> Option<Reply> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut send_buf = pdu::Buf::default();
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            for _ in 0..requests_per_connection {
+                let Some(msg) = read_framed(&mut stream) else {
+                    break;
+                };
+                let request = Pdu::from_bytes(&msg).unwrap();
+                let Some(reply) = handler(&request) else {
+                    continue;
+                };
+                encode_reply(&request, &reply, &mut send_buf);
+                let (head, tail) = send_buf.split_at(send_buf.len() / 2);
+                stream.write_all(head).unwrap();
+                thread::sleep(Duration::from_millis(5));
+                stream.write_all(tail).unwrap();
+            }
+        }
+    });
+    addr
+}
+
+/// Handler which serves GET, GETNEXT and GETBULK requests from an ordered MIB.
+fn mib_agent(mib: Vec<(Oid<'static>, OwnedValue)>) -> impl FnMut(&Pdu) -> Option<Reply> {
+    move |request| {
+        let next = |name: &Oid| {
+            mib.iter()
+                .find(|(o, _)| crate::walk::oid_cmp(o, name) == std::cmp::Ordering::Greater)
+                .cloned()
+        };
+        let names = request
+            .varbinds
+            .clone()
+            .map(|(name, _)| name.to_owned())
+            .collect::<Vec<_>>();
+        let v1 = request.version().unwrap() == Version::V1;
+        let mut varbinds = Vec::new();
+        match request.message_type {
+            MessageType::GetRequest => {
+                for name in names {
+                    let value = mib
+                        .iter()
+                        .find(|(o, _)| *o == name)
+                        .map_or(OwnedValue::NoSuchObject, |(_, v)| v.clone());
+                    varbinds.push((name, value));
+                }
+            }
+            MessageType::GetNextRequest => {
+                for (i, name) in names.into_iter().enumerate() {
+                    match next(&name) {
+                        Some(varbind) => varbinds.push(varbind),
+                        None if v1 => {
+                            return Some(Reply {
+                                error_status: snmp::ERRSTATUS_NOSUCHNAME,
+                                error_index: u32::try_from(i).unwrap() + 1,
+                                varbinds: vec![(name, OwnedValue::Null)],
+                            })
+                        }
+                        None => varbinds.push((name, OwnedValue::EndOfMibView)),
+                    }
+                }
+            }
+            MessageType::GetBulkRequest => {
+                let mut last = names;
+                for _ in 0..request.error_index {
+                    for name in &mut last {
+                        let varbind =
+                            next(name).unwrap_or((name.clone(), OwnedValue::EndOfMibView));
+                        *name = varbind.0.clone();
+                        varbinds.push(varbind);
+                    }
+                }
+            }
+            _ => return None,
+        }
+        Some(Reply::ok(varbinds))
+    }
+}
+
+fn system_mib() -> Vec<(Oid<'static>, OwnedValue)> {
+    let mut mib = vec![
+        (
+            oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]),
+            OwnedValue::OctetString(b"RoboPLC".to_vec()),
+        ),
+        (
+            oid(&[1, 3, 6, 1, 2, 1, 1, 2, 0]),
+            OwnedValue::ObjectIdentifier(oid(&[1, 3, 6, 1, 4, 1, 8072])),
+        ),
+        (
+            oid(&[1, 3, 6, 1, 2, 1, 1, 3, 0]),
+            OwnedValue::Timeticks(1234),
+        ),
+        (
+            oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]),
+            OwnedValue::OctetString(b"plc1".to_vec()),
+        ),
+    ];
+    for i in 1..=25 {
+        mib.push((
+            oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2, i]),
+            OwnedValue::OctetString(format!("eth{}", i).into_bytes()),
+        ));
+    }
+    mib.push((oid(&[1, 3, 6, 1, 2, 1, 4, 1, 0]), OwnedValue::Integer(2)));
+    mib
+}
+
+fn sync_session(version: Version, addr: SocketAddr) -> SyncSession {
+    let timeout = Some(Duration::from_secs(2));
+    match version {
+        Version::V1 => SyncSession::new_v1(addr, b"public", timeout, 0).unwrap(),
+        _ => SyncSession::new_v2c(addr, b"public", timeout, 0).unwrap(),
+    }
+}
+
+#[test]
+fn sync_get_many() {
+    let addr = spawn_agent(mib_agent(system_mib()));
+    let mut sess = sync_session(Version::V2C, addr);
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let sys_contact = oid(&[1, 3, 6, 1, 2, 1, 1, 4, 0]);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let resp = sess
+        .get_many(&[&sys_descr, &sys_contact, &sys_name])
+        .unwrap()
+        .to_owned_pdu();
+    assert_eq!(
+        resp.varbinds,
+        [
+            (sys_descr, OwnedValue::OctetString(b"RoboPLC".to_vec())),
+            (sys_contact, OwnedValue::NoSuchObject),
+            (sys_name, OwnedValue::OctetString(b"plc1".to_vec())),
+        ]
+    );
+}
+
+#[test]
+fn sync_walk_subtree() {
+    let addr = spawn_agent(mib_agent(system_mib()));
+    for version in [Version::V1, Version::V2C] {
+        let mut sess = sync_session(version, addr);
+        let if_descr = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2]);
+        let names = sess
+            .walk(&if_descr)
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>();
+        let expected = (1..=25)
+            .map(|i| oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2, i]))
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        let system = oid(&[1, 3, 6, 1, 2, 1, 1]);
+        assert_eq!(sess.walk(&system).count(), 4);
+    }
+}
+
+#[test]
+fn sync_walk_end_of_mib() {
+    let addr = spawn_agent(mib_agent(system_mib()));
+    for version in [Version::V1, Version::V2C] {
+        let mut sess = sync_session(version, addr);
+        let ip = oid(&[1, 3, 6, 1, 2, 1, 4]);
+        let varbinds = sess.walk(&ip).collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            varbinds,
+            [(oid(&[1, 3, 6, 1, 2, 1, 4, 1, 0]), OwnedValue::Integer(2))]
+        );
+    }
+}
+
+#[test]
+fn sync_walk_looping_agent() {
+    let addr = spawn_agent(|_| {
+        Some(Reply::ok(vec![
+            (oid(&[1, 3, 6, 1, 2, 1, 1, 3, 0]), OwnedValue::Timeticks(1)),
+            (oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]), OwnedValue::Timeticks(1)),
+        ]))
+    });
+    let mut sess = sync_session(Version::V2C, addr);
+    let mut walk = sess.walk(&oid(&[1, 3, 6, 1, 2, 1, 1]));
+    assert_eq!(
+        walk.next().unwrap().unwrap().0,
+        oid(&[1, 3, 6, 1, 2, 1, 1, 3, 0])
+    );
+    assert_eq!(walk.next().unwrap().unwrap_err(), Error::OidNotIncreasing);
+    assert!(walk.next().is_none());
+}
+
+#[test]
+fn sync_walk_error_status() {
+    let addr = spawn_agent(|_| {
+        Some(Reply {
+            error_status: snmp::ERRSTATUS_GENERR,
+            error_index: 1,
+            varbinds: Vec::new(),
+        })
+    });
+    let mut sess = sync_session(Version::V2C, addr);
+    let res = sess.walk(&oid(&[1, 3, 6, 1, 2, 1, 1])).collect::<Vec<_>>();
+    assert_eq!(
+        res,
+        [Err(Error::ErrorStatus {
+            status: snmp::ERRSTATUS_GENERR,
+            index: 1
+        })]
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_walk_subtree() {
+    use futures_util::StreamExt as _;
+
+    let addr = spawn_agent(mib_agent(system_mib()));
+    for version in [Version::V1, Version::V2C] {
+        let mut sess = match version {
+            Version::V1 => crate::AsyncSession::new_v1(addr, b"public", 0).await,
+            _ => crate::AsyncSession::new_v2c(addr, b"public", 0).await,
+        }
+        .unwrap();
+        let if_descr = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2]);
+        let names = sess
+            .walk(&if_descr)
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>()
+            .await;
+        let expected = (1..=25)
+            .map(|i| oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2, i]))
+            .collect::<Vec<_>>();
+        assert_eq!(names, expected);
+        let ip = oid(&[1, 3, 6, 1, 2, 1, 4]);
+        assert_eq!(sess.walk(&ip).count().await, 1);
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_walk_early_termination() {
+    use futures_util::StreamExt as _;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let mut handler = mib_agent(system_mib());
+    let addr = spawn_agent(move |pdu| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        handler(pdu)
+    });
+    let mut sess = crate::AsyncSession::new_v2c(addr, b"public", 0)
+        .await
+        .unwrap();
+    let if_descr = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2]);
+    let mut walk = sess.walk(&if_descr);
+    for _ in 0..3 {
+        walk.next().await.unwrap().unwrap();
+    }
+    drop(walk);
+    // one page of max-repetitions varbinds has been requested only
+    assert_eq!(requests.load(Ordering::SeqCst), 1);
+    let resp = sess.get(&oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0])).await.unwrap();
+    assert_eq!(resp.varbinds.count(), 1);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_walk_looping_agent() {
+    use futures_util::StreamExt as _;
+
+    let addr = spawn_agent(|request| {
+        let (name, _) = request.varbinds.clone().next().unwrap();
+        Some(Reply::ok(vec![(name.to_owned(), OwnedValue::Integer(1))]))
+    });
+    let mut sess = crate::AsyncSession::new_v2c(addr, b"public", 0)
+        .await
+        .unwrap();
+    let res = sess
+        .walk(&oid(&[1, 3, 6, 1, 2, 1, 1]))
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(res, [Err(Error::OidNotIncreasing)]);
+}
+
+/// ipAddrTable with 12 rows, ipAdEntNetMask is missing for 10.0.0.5
+fn ip_addr_table_mib() -> Vec<(Oid<'static>, OwnedValue)> {
+    let entry = [1, 3, 6, 1, 2, 1, 4, 20, 1];
+    let mut mib = Vec::new();
+    for column in 1..=3_u64 {
+        for i in 1..=12 {
+            if column == 3 && i == 5 {
+                continue;
+            }
+            let mut name = entry.to_vec();
+            name.extend([column, 10, 0, 0, i]);
+            let value = match column {
+                1 => OwnedValue::IpAddress([10, 0, 0, u8::try_from(i).unwrap()]),
+                2 => OwnedValue::Integer(i64::try_from(i).unwrap()),
+                _ => OwnedValue::IpAddress([255, 255, 255, 0]),
+            };
+            mib.push((oid(&name), value));
+        }
+    }
+    mib.push((
+        oid(&[1, 3, 6, 1, 2, 1, 4, 21, 1, 1, 0]),
+        OwnedValue::Integer(0),
+    ));
+    mib
+}
+
+fn check_ip_addr_table(table: &crate::Table) {
+    assert_eq!(table.columns, [1, 2, 3]);
+    assert_eq!(table.len(), 12);
+    for (n, (index, row)) in table.rows.iter().enumerate() {
+        let i = u64::try_from(n).unwrap() + 1;
+        assert_eq!(index, &[10, 0, 0, i]);
+        assert_eq!(row[1], Some(OwnedValue::Integer(i64::try_from(i).unwrap())));
+        assert_eq!(row[2].is_none(), i == 5);
+    }
+    assert_eq!(
+        table.value(&[10, 0, 0, 7], 1),
+        Some(&OwnedValue::IpAddress([10, 0, 0, 7]))
+    );
+    assert_eq!(table.value(&[10, 0, 0, 5], 3), None);
+    assert_eq!(table.value(&[10, 0, 0, 5], 4), None);
+}
+
+#[test]
+fn sync_table_sparse() {
+    let addr = spawn_agent(mib_agent(ip_addr_table_mib()));
+    let entry = oid(&[1, 3, 6, 1, 2, 1, 4, 20, 1]);
+    for version in [Version::V1, Version::V2C] {
+        let mut sess = sync_session(version, addr);
+        let table = sess.table(&entry, &[1, 2, 3]).unwrap();
+        check_ip_addr_table(&table);
+        let table = sess.table(&entry, &[4]).unwrap();
+        assert!(table.is_empty());
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_table_sparse() {
+    let addr = spawn_agent(mib_agent(ip_addr_table_mib()));
+    let entry = oid(&[1, 3, 6, 1, 2, 1, 4, 20, 1]);
+    for version in [Version::V1, Version::V2C] {
+        let mut sess = match version {
+            Version::V1 => crate::AsyncSession::new_v1(addr, b"public", 0).await,
+            _ => crate::AsyncSession::new_v2c(addr, b"public", 0).await,
+        }
+        .unwrap();
+        let table = sess.table(&entry, &[1, 2, 3]).await.unwrap();
+        check_ip_addr_table(&table);
+    }
+}
+
+#[test]
+fn retry_backoff() {
+    use crate::{Backoff, RetryPolicy};
+
+    let ms = Duration::from_millis;
+    let fixed = RetryPolicy::new(ms(100), 3);
+    assert_eq!(fixed.attempt_timeout(0), Some(ms(100)));
+    assert_eq!(fixed.attempt_timeout(3), Some(ms(100)));
+    let exp = fixed.with_backoff(Backoff::Exponential { max: ms(500) });
+    assert_eq!(exp.attempt_timeout(0), Some(ms(100)));
+    assert_eq!(exp.attempt_timeout(1), Some(ms(200)));
+    assert_eq!(exp.attempt_timeout(2), Some(ms(400)));
+    assert_eq!(exp.attempt_timeout(3), Some(ms(500)));
+    assert_eq!(exp.attempt_timeout(40), Some(ms(500)));
+    assert_eq!(RetryPolicy::default().attempt_timeout(0), None);
+}
+
+/// Agent which drops the first `drop` requests
+fn lossy_agent(drop: usize) -> SocketAddr {
+    let mut handler = mib_agent(system_mib());
+    let mut received = 0;
+    spawn_agent(move |request| {
+        received += 1;
+        if received > drop {
+            handler(request)
+        } else {
+            None
+        }
+    })
+}
+
+#[test]
+fn sync_retransmission() {
+    use crate::RetryPolicy;
+
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let mut sess = sync_session(Version::V2C, lossy_agent(2))
+        .with_retry_policy(RetryPolicy::new(Duration::from_millis(100), 2));
+    let mut resp = sess.get(&sys_descr).unwrap();
+    assert_eq!(
+        resp.varbinds.next().unwrap().1.to_owned_value(),
+        OwnedValue::OctetString(b"RoboPLC".to_vec())
+    );
+    let mut sess = sync_session(Version::V2C, lossy_agent(2));
+    sess.set_retry_policy(RetryPolicy::new(Duration::from_millis(100), 1));
+    assert_eq!(sess.get(&sys_descr).unwrap_err(), Error::Timeout);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_retransmission() {
+    use crate::{Backoff, RetryPolicy};
+
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let retry = RetryPolicy::new(Duration::from_millis(50), 2).with_backoff(Backoff::Exponential {
+        max: Duration::from_millis(200),
+    });
+    for (drop, ok) in [(2, true), (3, false)] {
+        let mut sess = crate::AsyncSession::new_v2c(lossy_agent(drop), b"public", 0)
+            .await
+            .unwrap();
+        sess.set_retry_policy(retry);
+        let res = sess.get(&sys_descr).await;
+        if ok {
+            assert_eq!(
+                res.unwrap().varbinds.next().unwrap().1.to_owned_value(),
+                OwnedValue::OctetString(b"RoboPLC".to_vec())
+            );
+        } else {
+            assert_eq!(res.unwrap_err(), Error::Timeout);
+        }
+    }
+}
+
+#[test]
+fn peek_message_id() {
+    let mut buf = pdu::Buf::default();
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    pdu::build_get(
+        Version::V2C,
+        b"public",
+        -12345,
+        &[&sys_descr],
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    assert_eq!(pdu::peek_message_id(&buf), Some(-12345));
+    #[cfg(feature = "v3")]
+    {
+        crate::v3::build_init(777, &mut buf);
+        assert_eq!(pdu::peek_message_id(&buf), Some(777));
+    }
+    assert_eq!(pdu::peek_message_id(&buf[..buf.len() / 2]), None);
+}
+
+/// Agent which answers the first request only after the given delay
+fn slow_agent(delay: Duration) -> SocketAddr {
+    let mut handler = mib_agent(system_mib());
+    let mut first = true;
+    spawn_agent(move |request| {
+        if first {
+            first = false;
+            thread::sleep(delay);
+        }
+        handler(request)
+    })
+}
+
+#[test]
+fn sync_stale_response_dropped() {
+    use crate::RetryPolicy;
+
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let mut sess = sync_session(Version::V2C, slow_agent(Duration::from_millis(300)));
+    sess.set_retry_policy(RetryPolicy::new(Duration::from_millis(100), 0));
+    assert_eq!(sess.get(&sys_descr).unwrap_err(), Error::Timeout);
+    // the late response to the first request arrives first and is dropped
+    sess.set_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0));
+    let mut resp = sess.get(&sys_name).unwrap();
+    assert_eq!(resp.varbinds.next().unwrap().0, sys_name);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_stale_response_dropped() {
+    use crate::RetryPolicy;
+
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let addr = slow_agent(Duration::from_millis(300));
+    let mut sess = crate::AsyncSession::new_v2c(addr, b"public", 0)
+        .await
+        .unwrap();
+    sess.set_retry_policy(RetryPolicy::new(Duration::from_millis(100), 0));
+    assert_eq!(sess.get(&sys_descr).await.unwrap_err(), Error::Timeout);
+    sess.set_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0));
+    let mut resp = sess.get(&sys_name).await.unwrap();
+    assert_eq!(resp.varbinds.next().unwrap().0, sys_name);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn shared_session_concurrent_requests() {
+    const REQUESTS: u64 = 8;
+
+    // the agent collects all requests first, then replies in the reverse order
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 65535];
+        let mut requests = Vec::new();
+        while requests.len() < usize::try_from(REQUESTS).unwrap() {
+            let (len, peer) = socket.recv_from(&mut buf).unwrap();
+            requests.push((buf[..len].to_vec(), peer));
+        }
+        let mut send_buf = pdu::Buf::default();
+        for (request, peer) in requests.iter().rev() {
+            let request = Pdu::from_bytes(request).unwrap();
+            let (name, _) = request.varbinds.clone().next().unwrap();
+            let arcs = name.iter().unwrap().collect::<Vec<_>>();
+            let value = Value::Integer(i64::try_from(*arcs.last().unwrap()).unwrap());
+            pdu::build(
+                Version::V2C,
+                request.community,
+                snmp::MSG_RESPONSE,
+                request.req_id,
+                &[(&name, value)],
+                0,
+                0,
+                &mut send_buf,
+                #[cfg(feature = "v3")]
+                None,
+            )
+            .unwrap();
+            socket.send_to(&send_buf, peer).unwrap();
+        }
+    });
+    let sess = crate::AsyncSession::new_v2c(addr, b"public", 0)
+        .await
+        .unwrap()
+        .into_shared();
+    let tasks = (1..=REQUESTS)
+        .map(|i| {
+            let sess = sess.clone();
+            tokio::spawn(async move {
+                let resp = sess.get(&oid(&[1, 3, 6, 1, 4, 1, i])).await.unwrap();
+                (i, resp)
+            })
+        })
+        .collect::<Vec<_>>();
+    for task in tasks {
+        let (i, resp) = task.await.unwrap();
+        assert_eq!(
+            resp.varbinds,
+            [(
+                oid(&[1, 3, 6, 1, 4, 1, i]),
+                OwnedValue::Integer(i64::try_from(i).unwrap())
+            )]
+        );
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn shared_session_timeout() {
+    use crate::RetryPolicy;
+
+    let mut sess = crate::AsyncSession::new_v2c(lossy_agent(2), b"public", 0)
+        .await
+        .unwrap();
+    sess.set_retry_policy(RetryPolicy::new(Duration::from_millis(100), 1));
+    let sess = sess.into_shared();
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    assert_eq!(sess.get(&sys_descr).await.unwrap_err(), Error::Timeout);
+    assert_eq!(
+        sess.get(&sys_descr).await.unwrap().varbinds[0].1,
+        OwnedValue::OctetString(b"RoboPLC".to_vec())
+    );
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn poller_multiple_targets() {
+    use crate::{Poller, RetryPolicy, Target};
+
+    let poller = Poller::bind("127.0.0.1:0", 0).await.unwrap();
+    let retry = RetryPolicy::new(Duration::from_millis(200), 1);
+    let mut targets = (0..4_i64)
+        .map(|n| {
+            let addr = spawn_agent(move |request| {
+                let (name, _) = request.varbinds.clone().next().unwrap();
+                Some(Reply::ok(vec![(name.to_owned(), OwnedValue::Integer(n))]))
+            });
+            Target::new_v2c(addr, b"public").with_retry_policy(retry)
+        })
+        .collect::<Vec<_>>();
+    // never replies
+    targets.push(Target::new_v1(lossy_agent(usize::MAX), b"public").with_retry_policy(retry));
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let tasks = targets
+        .into_iter()
+        .map(|target| {
+            let poller = poller.clone();
+            let sys_descr = sys_descr.clone();
+            tokio::spawn(async move {
+                let mut res = Vec::new();
+                for _ in 0..3 {
+                    res.push(poller.get(&target, &sys_descr).await);
+                }
+                res
+            })
+        })
+        .collect::<Vec<_>>();
+    for (n, task) in tasks.into_iter().enumerate() {
+        for res in task.await.unwrap() {
+            if n < 4 {
+                let resp = res.unwrap();
+                let n = i64::try_from(n).unwrap();
+                assert_eq!(resp.varbinds, [(sys_descr.clone(), OwnedValue::Integer(n))]);
+            } else {
+                assert_eq!(res.unwrap_err(), Error::Timeout);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_cancelled_requests() {
+    use crate::RetryPolicy;
+
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let addr = slow_agent(Duration::from_millis(200));
+    let mut sess = crate::AsyncSession::new_v2c(addr, b"public", 0)
+        .await
+        .unwrap();
+    // the first request is answered late, the others are cancelled right after sending
+    for _ in 0..3 {
+        let res = tokio::time::timeout(Duration::from_millis(20), sess.get(&sys_descr)).await;
+        assert!(res.is_err());
+    }
+    sess.set_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0));
+    for _ in 0..2 {
+        let mut resp = sess.get(&sys_name).await.unwrap();
+        assert_eq!(resp.varbinds.next().unwrap().0, sys_name);
+    }
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn v3_failed_response_keeps_security() {
+    use crate::v3::Security;
+
+    let engine_id = [0x80, 0x00, 0x1f, 0x88, 0x04];
+    let mut security = Security::new(b"user", b"password123")
+        .with_engine_id(&engine_id)
+        .unwrap()
+        .with_engine_boots_and_time(1, 100);
+    // newer engine boots, but a wrong user name
+    let agent_security = Security::new(b"other", b"password123")
+        .with_engine_id(&engine_id)
+        .unwrap()
+        .with_engine_boots_and_time(5, 100);
+    let mut buf = pdu::Buf::default();
+    pdu::build(
+        Version::V3,
+        b"",
+        snmp::MSG_RESPONSE,
+        1,
+        &[],
+        0,
+        0,
+        &mut buf,
+        Some(&agent_security),
+    )
+    .unwrap();
+    let bytes = buf.to_vec();
+    let res = Pdu::from_bytes_with_security(&bytes, Some(&mut security));
+    assert_eq!(
+        res.unwrap_err(),
+        Error::AuthFailure(crate::v3::AuthErrorKind::UsernameMismatch)
+    );
+    assert_eq!(security.engine_boots(), 1);
+    assert_eq!(security.engine_time(), 100);
+}
+
+#[test]
+fn session_builder() {
+    use crate::{RetryPolicy, SessionBuilder};
+    use std::sync::{Arc, Mutex};
+
+    let max_repetitions = Arc::new(Mutex::new(Vec::new()));
+    let seen = max_repetitions.clone();
+    let mut handler = mib_agent(system_mib());
+    let addr = spawn_agent(move |request| {
+        if request.message_type == MessageType::GetBulkRequest {
+            seen.lock().unwrap().push(request.error_index);
+        }
+        handler(request)
+    });
+    let builder = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 1))
+        .with_starting_req_id(1000)
+        .with_max_repetitions(5)
+        .with_local_addr("127.0.0.1:0".parse().unwrap())
+        .with_recv_buffer_size(1 << 20);
+    let mut sess = builder.build_sync(addr).unwrap();
+    assert_eq!(
+        sess.retry_policy(),
+        RetryPolicy::new(Duration::from_secs(2), 1)
+    );
+    let if_descr = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2]);
+    assert_eq!(sess.walk(&if_descr).count(), 25);
+    assert!(max_repetitions.lock().unwrap().iter().all(|n| *n == 5));
+    // responses larger than the max message size can not be decoded
+    let mut sess = builder.with_max_message_size(0).build_sync(addr).unwrap();
+    assert!(sess.getbulk(&[&if_descr], 0, 100).is_err());
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn session_builder_async() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let addr = spawn_agent(mib_agent(system_mib()));
+    let mut sess = SessionBuilder::v1(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 1))
+        .with_local_addr("127.0.0.1:0".parse().unwrap())
+        .build_async(addr)
+        .await
+        .unwrap();
+    assert_eq!(sess.version(), Version::V1);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let mut resp = sess.get(&sys_name).await.unwrap();
+    assert_eq!(resp.varbinds.next().unwrap().0, sys_name);
+}
+
+#[test]
+fn sync_local_bind() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let addr = spawn_agent(mib_agent(system_mib()));
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .with_local_addr("127.0.0.2:0".parse().unwrap())
+        .build_sync(addr)
+        .unwrap();
+    let local_addr = sess.local_addr().unwrap();
+    assert_eq!(local_addr.ip(), std::net::Ipv4Addr::new(127, 0, 0, 2));
+    assert_ne!(local_addr.port(), 0);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    assert_eq!(
+        sess.get(&sys_name).unwrap().varbinds.next().unwrap().0,
+        sys_name
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn sync_bind_device() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let addr = spawn_agent(mib_agent(system_mib()));
+    let builder = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .with_bind_device("lo");
+    let mut sess = match builder.build_sync(addr) {
+        Ok(sess) => sess,
+        // no CAP_NET_RAW
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => return,
+        Err(e) => panic!("{}", e),
+    };
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    assert_eq!(
+        sess.get(&sys_name).unwrap().varbinds.next().unwrap().0,
+        sys_name
+    );
+    let err = SessionBuilder::v2c(b"public")
+        .with_bind_device("snmp2-no-such-if")
+        .build_sync(addr)
+        .err()
+        .unwrap();
+    assert_ne!(err.kind(), std::io::ErrorKind::PermissionDenied);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_local_bind() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let addr = spawn_agent(mib_agent(system_mib()));
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .with_local_addr("127.0.0.3:0".parse().unwrap())
+        .build_async(addr)
+        .await
+        .unwrap();
+    let local_addr = sess.local_addr().unwrap();
+    assert_eq!(local_addr.ip(), std::net::Ipv4Addr::new(127, 0, 0, 3));
+    assert_ne!(local_addr.port(), 0);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let mut resp = sess.get(&sys_name).await.unwrap();
+    assert_eq!(resp.varbinds.next().unwrap().0, sys_name);
+}
+
+/// A bound socket which never replies, kept open for the test duration.
+fn silent_addr() -> (UdpSocket, SocketAddr) {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap();
+    (socket, addr)
+}
+
+#[test]
+fn sync_address_fallback() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let (_silent, silent) = silent_addr();
+    let agent = spawn_agent(mib_agent(system_mib()));
+    let addrs = [silent, agent];
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_millis(200), 0))
+        .build_sync(&addrs[..])
+        .unwrap();
+    assert_eq!(sess.peer_addr(), silent);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    assert_eq!(
+        sess.get(&sys_name).unwrap().varbinds.next().unwrap().0,
+        sys_name
+    );
+    assert_eq!(sess.peer_addr(), agent);
+    // the responding address is kept
+    let started = Instant::now();
+    sess.get(&sys_name).unwrap();
+    assert!(started.elapsed() < Duration::from_millis(200));
+}
+
+#[test]
+fn sync_resolve_interval() {
+    use crate::{RetryPolicy, SessionBuilder};
+    use std::{
+        net::ToSocketAddrs,
+        sync::{Arc, Mutex},
+        vec,
+    };
+
+    /// Destination whose address changes, like a DNS name.
+    #[derive(Clone)]
+    struct Moving(Arc<Mutex<SocketAddr>>);
+
+    impl ToSocketAddrs for Moving {
+        type Iter = vec::IntoIter<SocketAddr>;
+
+        fn to_socket_addrs(&self) -> std::io::Result<Self::Iter> {
+            Ok(vec![*self.0.lock().unwrap()].into_iter())
+        }
+    }
+
+    let first = spawn_agent(mib_agent(system_mib()));
+    let second = spawn_agent(mib_agent(system_mib()));
+    let destination = Moving(Arc::new(Mutex::new(first)));
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .build_sync_resolving(destination.clone(), Duration::ZERO)
+        .unwrap();
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    sess.get(&sys_name).unwrap();
+    assert_eq!(sess.peer_addr(), first);
+    *destination.0.lock().unwrap() = second;
+    sess.get(&sys_name).unwrap();
+    assert_eq!(sess.peer_addr(), second);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_address_fallback() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    // nothing listens on the port, the agent is unreachable
+    let (closed, closed_addr) = silent_addr();
+    drop(closed);
+    let agent = spawn_agent(mib_agent(system_mib()));
+    let addrs = [closed_addr, agent];
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .build_async(&addrs[..])
+        .await
+        .unwrap();
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    assert_eq!(
+        sess.get(&sys_name)
+            .await
+            .unwrap()
+            .varbinds
+            .next()
+            .unwrap()
+            .0,
+        sys_name
+    );
+    assert_eq!(sess.peer_addr(), agent);
+}
+
+#[test]
+fn sync_tcp() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let agent = spawn_tcp_agent(mib_agent(system_mib()), 3);
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 1))
+        .build_sync_tcp(agent)
+        .unwrap();
+    // the agent closes the connection after every third request
+    let if_descr = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2]);
+    let walked = sess.walk(&if_descr).collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(walked.len(), 25);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    for _ in 0..5 {
+        assert_eq!(
+            sess.get(&sys_name).unwrap().varbinds.next().unwrap().0,
+            sys_name
+        );
+    }
+    assert_eq!(sess.peer_addr(), agent);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_tcp() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let agent = spawn_tcp_agent(mib_agent(system_mib()), 3);
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 1))
+        .build_async_tcp(agent)
+        .await
+        .unwrap();
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    for _ in 0..5 {
+        assert_eq!(
+            sess.get(&sys_name)
+                .await
+                .unwrap()
+                .varbinds
+                .next()
+                .unwrap()
+                .0,
+            sys_name
+        );
+    }
+    let agent = spawn_tcp_agent(mib_agent(system_mib()), 100);
+    let sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .build_async_tcp(agent)
+        .await
+        .unwrap()
+        .into_shared();
+    let sys_descr = oid(&[1, 3, 6, 1, 2, 1, 1, 1, 0]);
+    let (name, descr) = tokio::join!(sess.get(&sys_name), sess.get(&sys_descr));
+    assert_eq!(name.unwrap().varbinds[0].0, sys_name);
+    assert_eq!(descr.unwrap().varbinds[0].0, sys_descr);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn shared_session_tcp_garbage() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    // the agent answers the request with a byte which does not start an SNMP message and keeps
+    // the connection open
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let agent = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        read_framed(&mut stream).unwrap();
+        stream.write_all(&[0]).unwrap();
+        thread::sleep(Duration::from_secs(10));
+    });
+    let sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(5), 0))
+        .build_async_tcp(agent)
+        .await
+        .unwrap()
+        .into_shared();
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let started = Instant::now();
+    assert_eq!(sess.get(&sys_name).await.unwrap_err(), Error::Receive);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    // the receive task has ended, the next requests fail at once
+    assert_eq!(sess.get(&sys_name).await.unwrap_err(), Error::Receive);
+}
+
+/// In-memory transport, which answers the requests with the handler like [`spawn_agent`].
+struct MockTransport<F> {
+    handler: F,
+    responses: std::collections::VecDeque<Vec<u8>>,
+    sent: usize,
+}
+
+impl<F> MockTransport<F>
+where
+    F: FnMut(&Pdu) -> Option<Reply>,
+{
+    fn new(handler: F) -> Self {
+        Self {
+            handler,
+            responses: std::collections::VecDeque::new(),
+            sent: 0,
+        }
+    }
+
+    fn handle(&mut self, msg: &[u8]) {
+        self.sent += 1;
+        let request = Pdu::from_bytes(msg).unwrap();
+        if let Some(reply) = (self.handler)(&request) {
+            let mut buf = Box::<pdu::Buf>::default();
+            encode_reply(&request, &reply, &mut buf);
+            self.responses.push_back(buf.to_vec());
+        }
+    }
+
+    fn take_response(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let response = self.responses.pop_front()?;
+        buf[..response.len()].copy_from_slice(&response);
+        Some(response.len())
+    }
+}
+
+impl<F> crate::Transport for MockTransport<F>
+where
+    F: FnMut(&Pdu) -> Option<Reply>,
+{
+    fn send(&mut self, msg: &[u8]) -> std::io::Result<()> {
+        self.handle(msg);
+        Ok(())
+    }
+
+    fn recv(&mut self, buf: &mut [u8], _deadline: Option<Instant>) -> std::io::Result<usize> {
+        self.take_response(buf)
+            .ok_or_else(|| std::io::ErrorKind::TimedOut.into())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<F> crate::AsyncTransport for MockTransport<F>
+where
+    F: FnMut(&Pdu) -> Option<Reply> + Send,
+{
+    fn send<'a>(&'a mut self, msg: &'a [u8]) -> crate::BoxFuture<'a, std::io::Result<()>> {
+        self.handle(msg);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn recv<'a>(&'a mut self, buf: &'a mut [u8]) -> crate::BoxFuture<'a, std::io::Result<usize>> {
+        let len = self.take_response(buf);
+        Box::pin(async move {
+            match len {
+                Some(len) => Ok(len),
+                None => std::future::pending().await,
+            }
+        })
+    }
+}
+
+#[test]
+fn sync_custom_transport() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let builder = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_millis(50), 1));
+    let mut sess = builder.build_sync_with(MockTransport::new(mib_agent(system_mib())));
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    assert_eq!(
+        sess.get(&sys_name).unwrap().varbinds.next().unwrap().0,
+        sys_name
+    );
+    let if_descr = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 2]);
+    assert_eq!(sess.walk(&if_descr).count(), 25);
+    assert!(sess.transport().sent > 1);
+
+    let mut sess = builder.build_sync_with(MockTransport::new(|_: &Pdu| None));
+    assert_eq!(sess.get(&sys_name).unwrap_err(), Error::Timeout);
+    assert_eq!(sess.transport().sent, 2);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_custom_transport() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let builder = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_millis(50), 1));
+    let mut sess = builder.build_async_with(MockTransport::new(mib_agent(system_mib())));
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    assert_eq!(
+        sess.get(&sys_name)
+            .await
+            .unwrap()
+            .varbinds
+            .next()
+            .unwrap()
+            .0,
+        sys_name
+    );
+
+    let mut sess = builder.build_async_with(MockTransport::new(|_: &Pdu| None));
+    assert_eq!(sess.get(&sys_name).await.unwrap_err(), Error::Timeout);
+    assert_eq!(sess.transport().sent, 2);
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn v3_tsm_message() {
+    use crate::v3::{AuthErrorKind, Security, LOCAL_ENGINE_ID};
+
+    let security = Security::tsm(b"alice");
+    assert_eq!(security.engine_id(), LOCAL_ENGINE_ID);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    let mut buf = pdu::Buf::default();
+    pdu::build_get(Version::V3, b"", 7, &[&sys_name], &mut buf, Some(&security)).unwrap();
+    let bytes = buf.to_vec();
+    let mut user_security = Security::new(b"alice", b"password123");
+    assert_eq!(
+        Pdu::from_bytes_with_security(&bytes, Some(&mut user_security)).unwrap_err(),
+        Error::AuthFailure(AuthErrorKind::UnsupportedUSM)
+    );
+    let mut agent_security = Security::tsm(b"bob");
+    let mut request = Pdu::from_bytes_with_security(&bytes, Some(&mut agent_security)).unwrap();
+    assert_eq!(request.message_type, MessageType::GetRequest);
+    assert_eq!(request.req_id, 7);
+    assert_eq!(request.community, b"bob");
+    assert_eq!(request.varbinds.next().unwrap().0, sys_name);
+}
+
+/// Self-signed certificate and key, with a DNS subjectAltName `<common name>.Example.com`.
+#[cfg(feature = "v3")]
+fn tls_identity(
+    common_name: &str,
+) -> (
+    openssl::x509::X509,
+    openssl::pkey::PKey<openssl::pkey::Private>,
+) {
+    tls_issued_identity(common_name, None, false)
+}
+
+/// Certificate and key like [`tls_identity`], signed by the issuer name and key if given, and
+/// a CA certificate if `ca` is set.
+#[cfg(feature = "v3")]
+fn tls_issued_identity(
+    common_name: &str,
+    issuer: Option<(
+        &openssl::x509::X509NameRef,
+        &openssl::pkey::PKeyRef<openssl::pkey::Private>,
+    )>,
+    ca: bool,
+) -> (
+    openssl::x509::X509,
+    openssl::pkey::PKey<openssl::pkey::Private>,
+) {
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::PKey,
+        x509::{
+            extension::{BasicConstraints, SubjectAlternativeName},
+            X509NameBuilder, X509,
+        },
+    };
+
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut name = X509NameBuilder::new().unwrap();
+    name.append_entry_by_nid(Nid::COMMONNAME, common_name)
+        .unwrap();
+    let name = name.build();
+    let (issuer_name, issuer_key) = issuer.unwrap_or((&name, &key));
+    let mut builder = X509::builder().unwrap();
+    builder.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    builder.set_serial_number(&serial).unwrap();
+    builder.set_subject_name(&name).unwrap();
+    builder.set_issuer_name(issuer_name).unwrap();
+    builder.set_pubkey(&key).unwrap();
+    builder
+        .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    builder
+        .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+        .unwrap();
+    if ca {
+        let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+        builder.append_extension(constraints).unwrap();
+    } else {
+        let alt_name = SubjectAlternativeName::new()
+            .dns(&format!("{}.Example.com", common_name))
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(alt_name).unwrap();
+    }
+    builder.sign(issuer_key, MessageDigest::sha256()).unwrap();
+    (builder.build(), key)
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn tls_certificate_map() {
+    use crate::{
+        tls::{CertificateMap, Fingerprint, SecurityNameMapping},
+        v3::AuthProtocol,
+    };
+
+    let (alice, _) = tls_identity("Alice");
+    let (bob, _) = tls_identity("Bob");
+    let alice_fingerprint = Fingerprint::of(&alice, AuthProtocol::Sha256).unwrap();
+    let bob_fingerprint = Fingerprint::of(&bob, AuthProtocol::Sha1).unwrap();
+    assert!(alice_fingerprint.matches(&alice));
+    assert!(!alice_fingerprint.matches(&bob));
+    let bytes = bob_fingerprint.to_bytes();
+    assert_eq!(bytes[0], 2);
+    assert_eq!(bytes.len(), 21);
+    assert_eq!(Fingerprint::from_bytes(&bytes).unwrap(), bob_fingerprint);
+    assert!(Fingerprint::from_bytes(&bytes[..20]).is_err());
+
+    let map = CertificateMap::new()
+        // no rfc822Name, skipped
+        .with_entry(
+            alice_fingerprint.clone(),
+            SecurityNameMapping::SanRfc822Name,
+        )
+        .with_entry(alice_fingerprint.clone(), SecurityNameMapping::SanDnsName)
+        .with_entry(alice_fingerprint, SecurityNameMapping::CommonName)
+        .with_entry(
+            bob_fingerprint,
+            SecurityNameMapping::Specified(b"operator".to_vec()),
+        );
+    assert_eq!(
+        map.security_name(&[alice.clone()]).unwrap(),
+        b"alice.example.com"
+    );
+    assert_eq!(map.security_name(&[bob]).unwrap(), b"operator");
+    let (carol, _) = tls_identity("Carol");
+    assert!(map.security_name(&[carol]).is_none());
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&alice, AuthProtocol::Sha512).unwrap(),
+        SecurityNameMapping::CommonName,
+    );
+    assert_eq!(map.security_name(&[alice]).unwrap(), b"Alice");
+
+    // certificates issued by a CA, not the ones only naming it as their issuer
+    let (ca_cert, ca_key) = tls_issued_identity("CA", None, true);
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&ca_cert, AuthProtocol::Sha256).unwrap(),
+        SecurityNameMapping::CommonName,
+    );
+    let (dave, _) = tls_issued_identity("Dave", Some((ca_cert.subject_name(), &ca_key)), false);
+    assert_eq!(
+        map.security_name(&[dave, ca_cert.clone()]).unwrap(),
+        b"Dave"
+    );
+    let (forger, forger_key) = tls_identity("forger");
+    let (eve, _) = tls_issued_identity("Eve", Some((ca_cert.subject_name(), &forger_key)), false);
+    assert!(map.security_name(&[eve, ca_cert, forger]).is_none());
+}
+
+/// Starts a local SNMPv3 over TLS agent, like [`spawn_tcp_agent`]. The agent maps the client
+/// certificates to security names with the map, the handler gets the security name as the
+/// request community.
+#[cfg(feature = "v3")]
+fn spawn_tls_agent<F>(
+    identity: (
+        openssl::x509::X509,
+        openssl::pkey::PKey<openssl::pkey::Private>,
+    ),
+    map: crate::tls::CertificateMap,
+    mut handler: F,
+    requests_per_connection: usize,
+) -> SocketAddr
+where
+    F: FnMut(&Pdu) -> Option<Reply> + Send + 'static,
+{
+    use openssl::ssl::{SslAcceptor, SslMethod, SslVerifyMode};
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    acceptor.set_certificate(&identity.0).unwrap();
+    acceptor.set_private_key(&identity.1).unwrap();
+    acceptor.set_session_id_context(b"snmp").unwrap();
+    // self-signed client certificates, the map decides which ones are known
+    acceptor.set_verify_callback(
+        SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        |_, _| true,
+    );
+    let acceptor = acceptor.build();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut send_buf = pdu::Buf::default();
+        for stream in listener.incoming() {
+            let Ok(mut stream) = acceptor.accept(stream.unwrap()) else {
+                continue;
+            };
+            let Some(name) = map.peer_security_name(stream.ssl()) else {
+                continue;
+            };
+            let mut security = crate::v3::Security::tsm(&name);
+            for _ in 0..requests_per_connection {
+                let Some(msg) = read_framed(&mut stream) else {
+                    break;
+                };
+                let request = Pdu::from_bytes_with_security(&msg, Some(&mut security)).unwrap();
+                let req_id = request.req_id;
+                let Some(reply) = handler(&request) else {
+                    continue;
+                };
+                let values = reply
+                    .varbinds
+                    .iter()
+                    .map(|(name, value)| (name, value.as_value()))
+                    .collect::<Vec<_>>();
+                pdu::build(
+                    Version::V3,
+                    b"",
+                    snmp::MSG_RESPONSE,
+                    req_id,
+                    &values,
+                    reply.error_status,
+                    reply.error_index,
+                    &mut send_buf,
+                    Some(&security),
+                )
+                .unwrap();
+                stream.write_all(&send_buf).unwrap();
+            }
+        }
+    });
+    addr
+}
+
+/// Replies to GET requests with the security name the agent has mapped the client to.
+#[cfg(feature = "v3")]
+fn security_name_agent() -> impl FnMut(&Pdu) -> Option<Reply> {
+    |request| {
+        let varbinds = request
+            .varbinds
+            .clone()
+            .map(|(name, _)| {
+                (
+                    name.to_owned(),
+                    OwnedValue::OctetString(request.community.to_vec()),
+                )
+            })
+            .collect();
+        Some(Reply::ok(varbinds))
+    }
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn sync_tls_tsm() {
+    use crate::{
+        tls::{CertificateMap, Fingerprint, SecurityNameMapping, TlsConfig, TlsTransport},
+        v3::{AuthProtocol, Security},
+        RetryPolicy, SessionBuilder,
+    };
+
+    let (agent_cert, agent_key) = tls_identity("agent");
+    let (client_cert, client_key) = tls_identity("Alice");
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&client_cert, AuthProtocol::Sha256).unwrap(),
+        SecurityNameMapping::SanDnsName,
+    );
+    let agent_fingerprint = Fingerprint::of(&agent_cert, AuthProtocol::Sha256).unwrap();
+    // the agent closes the connection after every second request
+    let agent = spawn_tls_agent(
+        (agent_cert.clone(), agent_key),
+        map,
+        security_name_agent(),
+        2,
+    );
+    let config = TlsConfig::new()
+        .with_certificate(client_cert, client_key)
+        .with_server_fingerprint(agent_fingerprint);
+    let timeout = Some(Duration::from_secs(2));
+    let transport = TlsTransport::connect(agent, &config, timeout).unwrap();
+    assert_eq!(transport.peer_certificates()[0], agent_cert);
+    let mut sess = SessionBuilder::v3(Security::tsm(b"alice.example.com"))
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 1))
+        .build_sync_with(transport);
+    // no engine discovery with TSM
+    sess.init().unwrap();
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    for _ in 0..5 {
+        let mut resp = sess.get(&sys_name).unwrap();
+        assert_eq!(resp.community, b"alice.example.com");
+        let (name, value) = resp.varbinds.next().unwrap();
+        assert_eq!(name, sys_name);
+        assert!(matches!(value, Value::OctetString(b"alice.example.com")));
+    }
+    assert!(sess.transport().session_reused());
+
+    // the fingerprint of another agent does not match
+    let other = spawn_tls_agent(
+        tls_identity("agent"),
+        CertificateMap::new(),
+        security_name_agent(),
+        1,
+    );
+    assert!(TlsTransport::connect(other, &config, timeout).is_err());
+}
+
+/// Starts a local TLS server which presents the certificate with the extra chain certificate,
+/// and closes the connections after the handshake.
+#[cfg(feature = "v3")]
+fn spawn_tls_server(
+    identity: (
+        openssl::x509::X509,
+        openssl::pkey::PKey<openssl::pkey::Private>,
+    ),
+    chain: openssl::x509::X509,
+) -> SocketAddr {
+    use openssl::ssl::{SslAcceptor, SslMethod};
+
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls_server()).unwrap();
+    acceptor.set_certificate(&identity.0).unwrap();
+    acceptor.set_private_key(&identity.1).unwrap();
+    acceptor.add_extra_chain_cert(chain).unwrap();
+    let acceptor = acceptor.build();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let _ = acceptor.accept(stream.unwrap());
+        }
+    });
+    addr
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn tls_pinned_ca() {
+    use crate::{
+        tls::{Fingerprint, TlsConfig, TlsTransport},
+        v3::AuthProtocol,
+    };
+
+    let (ca_cert, ca_key) = tls_issued_identity("CA", None, true);
+    let config = TlsConfig::new()
+        .with_server_fingerprint(Fingerprint::of(&ca_cert, AuthProtocol::Sha256).unwrap());
+    let timeout = Some(Duration::from_secs(2));
+    let agent = spawn_tls_server(
+        tls_issued_identity("agent", Some((ca_cert.subject_name(), &ca_key)), false),
+        ca_cert.clone(),
+    );
+    TlsTransport::connect(agent, &config, timeout).unwrap();
+
+    // a certificate naming the CA as its issuer, not signed by it
+    let (_, forger_key) = tls_identity("forger");
+    let forged = spawn_tls_server(
+        tls_issued_identity("agent", Some((ca_cert.subject_name(), &forger_key)), false),
+        ca_cert.clone(),
+    );
+    assert!(TlsTransport::connect(forged, &config, timeout).is_err());
+
+    // neither a fingerprint nor a server name to authenticate the agent with
+    let config = TlsConfig::new().with_ca_certificate(ca_cert);
+    assert!(TlsTransport::connect(agent, &config, timeout).is_err());
+}
+
+#[cfg(all(feature = "v3", feature = "tokio"))]
+#[tokio::test]
+async fn async_tls_tsm() {
+    use crate::{
+        tls::{AsyncTlsTransport, CertificateMap, Fingerprint, SecurityNameMapping, TlsConfig},
+        v3::{AuthProtocol, Security},
+        RetryPolicy, SessionBuilder,
+    };
+
+    let (agent_cert, agent_key) = tls_identity("agent");
+    let (client_cert, client_key) = tls_identity("Alice");
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&client_cert, AuthProtocol::Sha256).unwrap(),
+        SecurityNameMapping::CommonName,
+    );
+    let agent_fingerprint = Fingerprint::of(&agent_cert, AuthProtocol::Sha1).unwrap();
+    let agent = spawn_tls_agent((agent_cert, agent_key), map, security_name_agent(), 2);
+    let config = TlsConfig::new()
+        .with_certificate(client_cert, client_key)
+        .with_server_fingerprint(agent_fingerprint);
+    let transport = AsyncTlsTransport::connect(agent, &config, Some(Duration::from_secs(2)))
+        .await
+        .unwrap();
+    let mut sess = SessionBuilder::v3(Security::tsm(b"Alice"))
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 1))
+        .build_async_with(transport);
+    sess.init().await.unwrap();
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    for _ in 0..5 {
+        let mut resp = sess.get(&sys_name).await.unwrap();
+        let (name, value) = resp.varbinds.next().unwrap();
+        assert_eq!(name, sys_name);
+        assert!(matches!(value, Value::OctetString(b"Alice")));
+    }
+}
+
+/// Starts a local SNMPv3 over DTLS agent, like [`spawn_tls_agent`]. The agent forgets each
+/// association after the given number of requests, like after a restart.
+#[cfg(feature = "v3")]
+fn spawn_dtls_agent<F>(
+    identity: (
+        openssl::x509::X509,
+        openssl::pkey::PKey<openssl::pkey::Private>,
+    ),
+    map: crate::tls::CertificateMap,
+    mut handler: F,
+    requests_per_association: usize,
+) -> SocketAddr
+where
+    F: FnMut(&Pdu) -> Option<Reply> + Send + 'static,
+{
+    use openssl::ssl::{Ssl, SslContext, SslMethod, SslStream, SslVerifyMode};
+
+    /// Agent socket under an association, replies go to the last sender.
+    struct PeerDatagram {
+        socket: UdpSocket,
+        peer: Option<SocketAddr>,
+    }
+
+    impl Read for PeerDatagram {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let (len, peer) = self.socket.recv_from(buf)?;
+            self.peer = Some(peer);
+            Ok(len)
+        }
+    }
+
+    impl Write for PeerDatagram {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.socket.send_to(buf, self.peer.unwrap())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut context = SslContext::builder(SslMethod::dtls()).unwrap();
+    context.set_certificate(&identity.0).unwrap();
+    context.set_private_key(&identity.1).unwrap();
+    context.set_session_id_context(b"snmp").unwrap();
+    context.set_verify_callback(
+        SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+        |_, _| true,
+    );
+    let context = context.build();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let addr = socket.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 65535];
+        let mut send_buf = pdu::Buf::default();
+        loop {
+            let datagram = PeerDatagram {
+                socket: socket.try_clone().unwrap(),
+                peer: None,
+            };
+            let mut stream = SslStream::new(Ssl::new(&context).unwrap(), datagram).unwrap();
+            match stream.accept() {
+                Ok(()) => {}
+                // idle
+                Err(e) if e.io_error().is_some() => break,
+                Err(_) => continue,
+            }
+            let Some(name) = map.peer_security_name(stream.ssl()) else {
+                continue;
+            };
+            let mut security = crate::v3::Security::tsm(&name);
+            for _ in 0..requests_per_association {
+                let Ok(len) = stream.read(&mut buf) else {
+                    break;
+                };
+                let request =
+                    Pdu::from_bytes_with_security(&buf[..len], Some(&mut security)).unwrap();
+                let req_id = request.req_id;
+                let Some(reply) = handler(&request) else {
+                    continue;
+                };
+                let values = reply
+                    .varbinds
+                    .iter()
+                    .map(|(name, value)| (name, value.as_value()))
+                    .collect::<Vec<_>>();
+                pdu::build(
+                    Version::V3,
+                    b"",
+                    snmp::MSG_RESPONSE,
+                    req_id,
+                    &values,
+                    reply.error_status,
+                    reply.error_index,
+                    &mut send_buf,
+                    Some(&security),
+                )
+                .unwrap();
+                stream.write_all(&send_buf).unwrap();
+            }
+        }
+    });
+    addr
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn sync_dtls_tsm() {
+    use crate::{
+        tls::{CertificateMap, DtlsTransport, Fingerprint, SecurityNameMapping, TlsConfig},
+        v3::{AuthProtocol, Security},
+        RetryPolicy, SessionBuilder,
+    };
+
+    let (agent_cert, agent_key) = tls_identity("agent");
+    let (client_cert, client_key) = tls_identity("Alice");
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&client_cert, AuthProtocol::Sha256).unwrap(),
+        SecurityNameMapping::CommonName,
+    );
+    let agent_fingerprint = Fingerprint::of(&agent_cert, AuthProtocol::Sha256).unwrap();
+    let agent = spawn_dtls_agent((agent_cert, agent_key), map, security_name_agent(), 2);
+    let config = TlsConfig::new()
+        .with_certificate(client_cert, client_key)
+        .with_server_fingerprint(agent_fingerprint);
+    let transport = DtlsTransport::connect(agent, &config, Some(Duration::from_secs(2))).unwrap();
+    assert!(!transport.session_reused());
+    let mut sess = SessionBuilder::v3(Security::tsm(b"Alice"))
+        .with_retry_policy(RetryPolicy::new(Duration::from_millis(300), 1))
+        .build_sync_with(transport);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    for _ in 0..3 {
+        let mut resp = sess.get(&sys_name).unwrap();
+        let (name, value) = resp.varbinds.next().unwrap();
+        assert_eq!(name, sys_name);
+        assert!(matches!(value, Value::OctetString(b"Alice")));
+    }
+    // the agent has lost the first association, the session has been resumed
+    assert!(sess.transport().session_reused());
+}
+
+#[cfg(all(feature = "v3", feature = "tokio"))]
+#[tokio::test]
+async fn async_dtls_tsm() {
+    use crate::{
+        tls::{AsyncDtlsTransport, CertificateMap, Fingerprint, SecurityNameMapping, TlsConfig},
+        v3::{AuthProtocol, Security},
+        RetryPolicy, SessionBuilder,
+    };
+
+    let (agent_cert, agent_key) = tls_identity("agent");
+    let (client_cert, client_key) = tls_identity("Alice");
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&client_cert, AuthProtocol::Sha256).unwrap(),
+        SecurityNameMapping::SanDnsName,
+    );
+    let agent_fingerprint = Fingerprint::of(&agent_cert, AuthProtocol::Sha256).unwrap();
+    let agent = spawn_dtls_agent((agent_cert, agent_key), map, security_name_agent(), 2);
+    let config = TlsConfig::new()
+        .with_certificate(client_cert, client_key)
+        .with_server_fingerprint(agent_fingerprint);
+    let transport = AsyncDtlsTransport::connect(agent, &config, Some(Duration::from_secs(2)))
+        .await
+        .unwrap();
+    let mut sess = SessionBuilder::v3(Security::tsm(b"alice.example.com"))
+        .with_retry_policy(RetryPolicy::new(Duration::from_millis(300), 1))
+        .build_async_with(transport);
+    let sys_name = oid(&[1, 3, 6, 1, 2, 1, 1, 5, 0]);
+    for _ in 0..3 {
+        let mut resp = sess.get(&sys_name).await.unwrap();
+        let (name, value) = resp.varbinds.next().unwrap();
+        assert_eq!(name, sys_name);
+        assert!(matches!(value, Value::OctetString(b"alice.example.com")));
+    }
+    assert!(sess.transport().session_reused());
+}
+
+#[test]
+fn oid_ordering() {
+    use std::cmp::Ordering;
+
+    assert_eq!(
+        crate::walk::oid_cmp(&oid(&[1, 3, 6, 16383]), &oid(&[1, 3, 6, 16384])),
+        Ordering::Less
+    );
+    assert_eq!(
+        crate::walk::oid_cmp(&oid(&[1, 3, 6, 1]), &oid(&[1, 3, 6, 1, 0])),
+        Ordering::Less
+    );
+    assert_eq!(
+        crate::walk::oid_cmp(&oid(&[1, 3, 6, 2]), &oid(&[1, 3, 6, 1, 5])),
+        Ordering::Greater
+    );
+}
+
 #[test]
 #[cfg(feature = "mibs")]
 fn test_mib() {
@@ -146,3 +2324,644 @@ fn test_mib() {
     let snmp_oid2 = Oid::from_mib_name(&name).unwrap();
     assert_eq!(snmp_oid, snmp_oid2);
 }
+
+/// SNMPv1 trap message, encoded field by field.
+fn v1_trap_message(community: &[u8]) -> pdu::Buf {
+    let mut buf = pdu::Buf::default();
+    buf.push_sequence(|buf| {
+        buf.push_constructed(snmp::MSG_TRAP_V1, |buf| {
+            buf.push_sequence(|buf| {
+                buf.push_sequence(|buf| {
+                    buf.push_octet_string(b"overheat");
+                    buf.push_object_identifier(&[1, 3, 6, 1, 4, 1, 8072, 2, 1]);
+                });
+            });
+            buf.push_timeticks(1234);
+            buf.push_integer(7);
+            buf.push_integer(6);
+            buf.push_ipaddress([192, 0, 2, 1]);
+            buf.push_object_identifier(&[1, 3, 6, 1, 4, 1, 8072]);
+        });
+        buf.push_octet_string(community);
+        buf.push_integer(Version::V1 as i64);
+    });
+    buf
+}
+
+#[test]
+fn build_trap_v1_pdu() {
+    use crate::pdu::V1TrapInfo;
+
+    let mut info = V1TrapInfo {
+        enterprise: oid(&[1, 3, 6, 1, 4, 1, 8072]),
+        agent_addr: [192, 0, 2, 1].into(),
+        generic_trap: snmp::TRAP_ENTERPRISE_SPECIFIC,
+        specific_trap: 7,
+        timestamp: 1234,
+    };
+    let mut buf = pdu::Buf::default();
+    pdu::build_trap_v1(
+        b"public",
+        &info,
+        &[(
+            &oid(&[1, 3, 6, 1, 4, 1, 8072, 2, 1]),
+            Value::OctetString(b"overheat"),
+        )],
+        &mut buf,
+    )
+    .unwrap();
+    assert_eq!(&buf[..], &v1_trap_message(b"public")[..]);
+    // the agent address of SNMPv1 traps is IPv4 only
+    info.agent_addr = std::net::Ipv6Addr::LOCALHOST.into();
+    assert_eq!(
+        pdu::build_trap_v1(b"public", &info, &[], &mut buf),
+        Err(Error::ValueOutOfRange)
+    );
+}
+
+/// SNMPv2c linkDown trap or inform request.
+fn v2c_notification(ident: u8, req_id: i32) -> pdu::Buf {
+    let sys_uptime = oid(&[1, 3, 6, 1, 2, 1, 1, 3, 0]);
+    let trap_oid = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0]);
+    let link_down = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 3]);
+    let mut buf = pdu::Buf::default();
+    pdu::build(
+        Version::V2C,
+        b"public",
+        ident,
+        req_id,
+        &[
+            (&sys_uptime, Value::Timeticks(100)),
+            (&trap_oid, Value::ObjectIdentifier(link_down)),
+        ],
+        0,
+        0,
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    buf
+}
+
+#[test]
+fn trap_listener() {
+    use crate::{pdu::V1TrapInfo, TrapListener};
+    use std::sync::mpsc;
+
+    let (errors_tx, errors) = mpsc::channel();
+    let mut listener = TrapListener::bind("127.0.0.1:0")
+        .unwrap()
+        .with_error_handler(move |source, error| errors_tx.send((source, error.clone())).unwrap());
+    let addr = listener.local_addr().unwrap();
+    let shutdown = listener.shutdown_handle();
+    let (notifications_tx, notifications) = mpsc::channel();
+    let listening = thread::spawn(move || {
+        listener.run(|source, pdu| notifications_tx.send((source, pdu.to_owned_pdu())).unwrap())
+    });
+
+    let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let source = agent.local_addr().unwrap();
+    agent.send_to(&v1_trap_message(b"public"), addr).unwrap();
+    for (ident, req_id) in [(snmp::MSG_TRAP, 1), (snmp::MSG_INFORM, 2)] {
+        agent
+            .send_to(&v2c_notification(ident, req_id), addr)
+            .unwrap();
+    }
+    // malformed and not a notification
+    agent.send_to(&[0x30, 0x03, 0x02, 0x01], addr).unwrap();
+    let mut buf = pdu::Buf::default();
+    pdu::build_get(
+        Version::V2C,
+        b"public",
+        3,
+        &[&oid(&[1, 3, 6, 1, 2, 1, 1, 3, 0])],
+        &mut buf,
+        #[cfg(feature = "v3")]
+        None,
+    )
+    .unwrap();
+    agent.send_to(&buf, addr).unwrap();
+
+    let timeout = Duration::from_secs(2);
+    let (from, trap) = notifications.recv_timeout(timeout).unwrap();
+    assert_eq!(from, source);
+    assert_eq!(trap.version().unwrap(), Version::V1);
+    assert_eq!(trap.message_type, MessageType::TrapV1);
+    assert_eq!(
+        trap.v1_trap_info,
+        Some(V1TrapInfo {
+            enterprise: oid(&[1, 3, 6, 1, 4, 1, 8072]),
+            agent_addr: [192, 0, 2, 1].into(),
+            generic_trap: 6,
+            specific_trap: 7,
+            timestamp: 1234,
+        })
+    );
+    assert_eq!(
+        trap.varbinds,
+        [(
+            oid(&[1, 3, 6, 1, 4, 1, 8072, 2, 1]),
+            OwnedValue::OctetString(b"overheat".to_vec())
+        )]
+    );
+    for (message_type, req_id) in [(MessageType::Trap, 1), (MessageType::InformRequest, 2)] {
+        let (from, notification) = notifications.recv_timeout(timeout).unwrap();
+        assert_eq!(from, source);
+        assert_eq!(notification.version().unwrap(), Version::V2C);
+        assert_eq!(notification.message_type, message_type);
+        assert_eq!(notification.req_id, req_id);
+        assert_eq!(notification.community, b"public");
+        assert_eq!(
+            notification.varbinds[1].0,
+            oid(&[1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0])
+        );
+    }
+    let (from, _) = errors.recv_timeout(timeout).unwrap();
+    assert_eq!(from, source);
+    assert_eq!(
+        errors.recv_timeout(timeout).unwrap(),
+        (source, Error::AsnWrongType)
+    );
+    // only the inform request is acknowledged
+    agent.set_read_timeout(Some(timeout)).unwrap();
+    let mut ack = [0; 1024];
+    let (len, from) = agent.recv_from(&mut ack).unwrap();
+    assert_eq!(from, addr);
+    let response = Pdu::from_bytes(&ack[..len]).unwrap();
+    assert_eq!(response.version().unwrap(), Version::V2C);
+    assert_eq!(response.message_type, MessageType::Response);
+    assert_eq!(response.req_id, 2);
+    assert_eq!(response.community, b"public");
+    assert_eq!(
+        response.varbinds.map(|(oid, _)| oid).collect::<Vec<_>>(),
+        [
+            oid(&[1, 3, 6, 1, 2, 1, 1, 3, 0]),
+            oid(&[1, 3, 6, 1, 6, 3, 1, 1, 4, 1, 0])
+        ]
+    );
+    agent
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    assert!(agent.recv_from(&mut ack).is_err());
+
+    shutdown.shutdown();
+    listening.join().unwrap().unwrap();
+    assert!(notifications.try_recv().is_err());
+}
+
+/// Security of the receiver of informs, which is their authoritative engine, and of the senders,
+/// which have discovered it.
+#[cfg(feature = "v3")]
+fn inform_security() -> crate::v3::Security {
+    use crate::v3::{Auth, AuthProtocol, Cipher, Security};
+
+    Security::new(b"user", b"password123")
+        .with_auth_protocol(AuthProtocol::Sha1)
+        .with_auth(Auth::AuthPriv {
+            cipher: Cipher::Aes128,
+            privacy_password: b"password456".to_vec(),
+        })
+        .with_engine_id(&[0x80, 0x00, 0x1f, 0x88, 0x04])
+        .unwrap()
+        .with_engine_boots_and_time(3, 1000)
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn v3_inform_acknowledged() {
+    use crate::TrapListener;
+
+    let mut listener = TrapListener::bind("127.0.0.1:0")
+        .unwrap()
+        .with_security(inform_security());
+    let addr = listener.local_addr().unwrap();
+    let shutdown = listener.shutdown_handle();
+    let (notifications_tx, notifications) = std::sync::mpsc::channel();
+    let listening = thread::spawn(move || {
+        listener.run(|_, pdu| notifications_tx.send(pdu.to_owned_pdu()).unwrap())
+    });
+
+    let mut sender_security = inform_security();
+    let sys_uptime = oid(&[1, 3, 6, 1, 2, 1, 1, 3, 0]);
+    let mut buf = pdu::Buf::default();
+    // msgID and request ID differ, as with most senders
+    crate::v3::build(
+        snmp::MSG_INFORM,
+        77,
+        5,
+        &[(&sys_uptime, Value::Timeticks(100))],
+        0,
+        0,
+        &mut buf,
+        Some(&sender_security),
+    )
+    .unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.send_to(&buf, addr).unwrap();
+    sender
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let mut ack = [0; 1024];
+    let len = sender.recv(&mut ack).unwrap();
+    let mut response =
+        Pdu::from_bytes_with_security(&ack[..len], Some(&mut sender_security)).unwrap();
+    assert_eq!(response.message_type, MessageType::Response);
+    assert_eq!(response.v3_msg_id, 77);
+    assert_eq!(response.req_id, 5);
+    assert_eq!(response.community, b"user");
+    assert_eq!(response.varbinds.next().unwrap().0, sys_uptime);
+
+    let inform = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(inform.version().unwrap(), Version::V3);
+    assert_eq!(inform.message_type, MessageType::InformRequest);
+    assert_eq!(inform.community, b"user");
+    shutdown.shutdown();
+    listening.join().unwrap().unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_trap_listener() {
+    use crate::AsyncTrapListener;
+    use futures_util::StreamExt as _;
+
+    let listener = AsyncTrapListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .with_queue_size(1);
+    let addr = listener.local_addr().unwrap();
+    let mut notifications = listener.into_stream();
+    let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let source = agent.local_addr().unwrap();
+    let timeout = Duration::from_secs(2);
+
+    // the listener stops reading while the queue is full, nothing is lost
+    for req_id in 1..=3 {
+        agent
+            .send_to(&v2c_notification(snmp::MSG_TRAP, req_id), addr)
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    for req_id in 1..=3 {
+        let notification = tokio::time::timeout(timeout, notifications.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(notification.source, source);
+        assert_eq!(notification.pdu.version().unwrap(), Version::V2C);
+        assert_eq!(notification.pdu.community, b"public");
+        assert_eq!(notification.pdu.message_type, MessageType::Trap);
+        assert_eq!(notification.pdu.req_id, req_id);
+        assert_eq!(notification.pdu.varbinds.len(), 2);
+    }
+
+    // the queued notification is still yielded after shutdown
+    agent
+        .send_to(&v2c_notification(snmp::MSG_INFORM, 4), addr)
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    notifications.shutdown_handle().shutdown();
+    let notification = tokio::time::timeout(timeout, notifications.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(notification.pdu.message_type, MessageType::InformRequest);
+    assert_eq!(notification.pdu.req_id, 4);
+    agent.set_read_timeout(Some(timeout)).unwrap();
+    let mut ack = [0; 1024];
+    let len = agent.recv(&mut ack).unwrap();
+    let response = Pdu::from_bytes(&ack[..len]).unwrap();
+    assert_eq!(response.message_type, MessageType::Response);
+    assert_eq!(response.req_id, 4);
+    assert!(tokio::time::timeout(timeout, notifications.next())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+/// Spawns a trap listener, which passes the received notifications to the channel.
+fn spawn_trap_listener() -> (
+    SocketAddr,
+    crate::ShutdownHandle,
+    std::sync::mpsc::Receiver<crate::OwnedPdu>,
+) {
+    spawn_listener(crate::TrapListener::bind("127.0.0.1:0").unwrap())
+}
+
+fn spawn_listener(
+    mut listener: crate::TrapListener,
+) -> (
+    SocketAddr,
+    crate::ShutdownHandle,
+    std::sync::mpsc::Receiver<crate::OwnedPdu>,
+) {
+    let addr = listener.local_addr().unwrap();
+    let shutdown = listener.shutdown_handle();
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || listener.run(|_, pdu| tx.send(pdu.to_owned_pdu()).unwrap()));
+    (addr, shutdown, rx)
+}
+
+#[test]
+fn sync_send_traps() {
+    use crate::{pdu::V1TrapInfo, SessionBuilder};
+
+    let (addr, shutdown, notifications) = spawn_trap_listener();
+    let timeout = Duration::from_secs(2);
+    let link_down = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 3]);
+    let if_index = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 1, 2]);
+
+    let mut sess = SessionBuilder::v1(b"public").build_sync(addr).unwrap();
+    let info = V1TrapInfo {
+        enterprise: oid(&[1, 3, 6, 1, 4, 1, 8072]),
+        agent_addr: [192, 0, 2, 1].into(),
+        generic_trap: snmp::TRAP_LINK_DOWN,
+        specific_trap: 0,
+        timestamp: 4200,
+    };
+    sess.trap_v1(&info, &[(&if_index, Value::Integer(2))])
+        .unwrap();
+    assert_eq!(
+        sess.trap(&link_down, 4200, &[]),
+        Err(Error::UnsupportedVersion)
+    );
+    let trap = notifications.recv_timeout(timeout).unwrap();
+    assert_eq!(trap.message_type, MessageType::TrapV1);
+    assert_eq!(trap.community, b"public");
+    assert_eq!(trap.v1_trap_info, Some(info.clone()));
+    assert_eq!(trap.varbinds, [(if_index.clone(), OwnedValue::Integer(2))]);
+
+    let mut sess = SessionBuilder::v2c(b"public").build_sync(addr).unwrap();
+    sess.trap(&link_down, 4200, &[(&if_index, Value::Integer(2))])
+        .unwrap();
+    assert_eq!(sess.trap_v1(&info, &[]), Err(Error::UnsupportedVersion));
+    let trap = notifications.recv_timeout(timeout).unwrap();
+    assert_eq!(trap.version().unwrap(), Version::V2C);
+    assert_eq!(trap.message_type, MessageType::Trap);
+    assert_eq!(
+        trap.varbinds,
+        [
+            (snmp::SYS_UPTIME, OwnedValue::Timeticks(4200)),
+            (snmp::SNMP_TRAP_OID, OwnedValue::ObjectIdentifier(link_down)),
+            (if_index, OwnedValue::Integer(2)),
+        ]
+    );
+    shutdown.shutdown();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_send_trap() {
+    use crate::SessionBuilder;
+
+    let (addr, shutdown, notifications) = spawn_trap_listener();
+    let cold_start = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 1]);
+    let mut sess = SessionBuilder::v2c(b"public")
+        .build_async(addr)
+        .await
+        .unwrap();
+    sess.trap(&cold_start, 0, &[]).await.unwrap();
+    let trap = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(trap.message_type, MessageType::Trap);
+    assert_eq!(
+        trap.varbinds,
+        [
+            (snmp::SYS_UPTIME, OwnedValue::Timeticks(0)),
+            (
+                snmp::SNMP_TRAP_OID,
+                OwnedValue::ObjectIdentifier(cold_start)
+            ),
+        ]
+    );
+    shutdown.shutdown();
+}
+
+#[test]
+fn sync_inform() {
+    use crate::{Backoff, RetryPolicy, SessionBuilder};
+
+    let (addr, shutdown, notifications) = spawn_trap_listener();
+    let link_down = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 3]);
+    let if_index = oid(&[1, 3, 6, 1, 2, 1, 2, 2, 1, 1, 2]);
+    let retry =
+        RetryPolicy::new(Duration::from_millis(100), 2).with_backoff(Backoff::Exponential {
+            max: Duration::from_millis(400),
+        });
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(retry)
+        .build_sync(addr)
+        .unwrap();
+    let response = sess
+        .inform(&link_down, 4200, &[(&if_index, Value::Integer(2))])
+        .unwrap();
+    assert_eq!(response.message_type, MessageType::Response);
+    assert_eq!(
+        response.varbinds.map(|(oid, _)| oid).collect::<Vec<_>>(),
+        [snmp::SYS_UPTIME, snmp::SNMP_TRAP_OID, if_index.clone()]
+    );
+    let inform = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(inform.message_type, MessageType::InformRequest);
+    assert_eq!(inform.varbinds.len(), 3);
+    shutdown.shutdown();
+
+    // not acknowledged: sent three times, then reported as not delivered
+    let (silent, silent_addr) = silent_addr();
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(retry)
+        .build_sync(silent_addr)
+        .unwrap();
+    let started = Instant::now();
+    assert_eq!(
+        sess.inform(&link_down, 4200, &[]).unwrap_err(),
+        Error::Timeout
+    );
+    // 100 + 200 + 400 ms
+    assert!(started.elapsed() >= Duration::from_millis(700));
+    silent.set_nonblocking(true).unwrap();
+    let mut buf = [0; 1024];
+    let mut attempts = 0;
+    while let Ok(len) = silent.recv(&mut buf) {
+        let inform = Pdu::from_bytes(&buf[..len]).unwrap();
+        assert_eq!(inform.message_type, MessageType::InformRequest);
+        attempts += 1;
+    }
+    assert_eq!(attempts, 3);
+
+    let mut sess = SessionBuilder::v1(b"public").build_sync(addr).unwrap();
+    assert_eq!(
+        sess.inform(&link_down, 4200, &[]).unwrap_err(),
+        Error::UnsupportedVersion
+    );
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn sync_v3_inform() {
+    use crate::{RetryPolicy, SessionBuilder, TrapListener};
+
+    let (addr, shutdown, notifications) = spawn_listener(
+        TrapListener::bind("127.0.0.1:0")
+            .unwrap()
+            .with_security(inform_security()),
+    );
+    let link_up = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 4]);
+    let mut sess = SessionBuilder::v3(inform_security())
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .build_sync(addr)
+        .unwrap();
+    for uptime in [100, 200] {
+        let response = sess.inform(&link_up, uptime, &[]).unwrap();
+        assert_eq!(response.message_type, MessageType::Response);
+        assert_eq!(response.community, b"user");
+        let inform = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+        assert_eq!(inform.version().unwrap(), Version::V3);
+        assert_eq!(
+            inform.varbinds[0],
+            (snmp::SYS_UPTIME, OwnedValue::Timeticks(uptime))
+        );
+    }
+    shutdown.shutdown();
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn dtls_trap_listener() {
+    use crate::{
+        tls::{CertificateMap, DtlsTransport, Fingerprint, SecurityNameMapping, TlsConfig},
+        v3::{AuthProtocol, Security},
+        DtlsTrapListener, RetryPolicy, SessionBuilder,
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    let (receiver_cert, receiver_key) = tls_identity("receiver");
+    let (agent_cert, agent_key) = tls_identity("Alice");
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&agent_cert, AuthProtocol::Sha256).unwrap(),
+        SecurityNameMapping::SanDnsName,
+    );
+    let errors = Arc::new(AtomicUsize::new(0));
+    let failed = errors.clone();
+    let mut listener = DtlsTrapListener::bind("127.0.0.1:0", &receiver_cert, &receiver_key, map)
+        .unwrap()
+        .with_error_handler(move |_, _| {
+            failed.fetch_add(1, Ordering::SeqCst);
+        });
+    let addr = listener.local_addr().unwrap();
+    let shutdown = listener.shutdown_handle();
+    let (tx, notifications) = std::sync::mpsc::channel();
+    thread::spawn(move || listener.run(|_, pdu| tx.send(pdu.to_owned_pdu()).unwrap()));
+
+    let receiver_fingerprint = Fingerprint::of(&receiver_cert, AuthProtocol::Sha256).unwrap();
+    let config = TlsConfig::new()
+        .with_certificate(agent_cert, agent_key)
+        .with_server_fingerprint(receiver_fingerprint);
+    let timeout = Some(Duration::from_secs(2));
+    let transport = DtlsTransport::connect(addr, &config, timeout).unwrap();
+    let mut sess = SessionBuilder::v3(Security::tsm(b"alice.example.com"))
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .build_sync_with(transport);
+    let link_up = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 4]);
+    sess.trap(&link_up, 100, &[]).unwrap();
+    let trap = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(trap.message_type, MessageType::Trap);
+    assert_eq!(trap.version().unwrap(), Version::V3);
+    assert_eq!(trap.community, b"alice.example.com");
+    let response = sess.inform(&link_up, 200, &[]).unwrap();
+    assert_eq!(response.message_type, MessageType::Response);
+    let inform = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(inform.message_type, MessageType::InformRequest);
+    assert_eq!(
+        inform.varbinds[0],
+        (snmp::SYS_UPTIME, OwnedValue::Timeticks(200))
+    );
+    assert_eq!(errors.load(Ordering::SeqCst), 0);
+
+    // agents with unknown certificates fail the handshake
+    let (other_cert, other_key) = tls_identity("Alice");
+    let config = config.with_certificate(other_cert, other_key);
+    assert!(DtlsTransport::connect(addr, &config, timeout).is_err());
+    assert_eq!(errors.load(Ordering::SeqCst), 1);
+    shutdown.shutdown();
+}
+
+#[cfg(feature = "v3")]
+#[test]
+fn dtls_trap_listener_cookie() {
+    use crate::{
+        tls::{self, CertificateMap, DtlsTransport, Fingerprint, SecurityNameMapping, TlsConfig},
+        v3::{AuthProtocol, Security},
+        DtlsTrapListener, RetryPolicy, SessionBuilder,
+    };
+    use openssl::ssl::{Ssl, SslContext, SslMethod, SslStream};
+
+    let (receiver_cert, receiver_key) = tls_identity("receiver");
+    let (agent_cert, agent_key) = tls_identity("Alice");
+    let map = CertificateMap::new().with_entry(
+        Fingerprint::of(&agent_cert, AuthProtocol::Sha256).unwrap(),
+        SecurityNameMapping::SanDnsName,
+    );
+    let mut listener =
+        DtlsTrapListener::bind("127.0.0.1:0", &receiver_cert, &receiver_key, map).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let shutdown = listener.shutdown_handle();
+    let (tx, notifications) = std::sync::mpsc::channel();
+    thread::spawn(move || listener.run(|_, pdu| tx.send(pdu.to_owned_pdu()).unwrap()));
+
+    let context = SslContext::builder(SslMethod::dtls()).unwrap().build();
+    let mut client = SslStream::new(Ssl::new(&context).unwrap(), tls::Channel::default()).unwrap();
+    assert!(client.connect().is_err());
+    let client_hello = client.get_mut().drain().next().unwrap();
+    // more ClientHello messages without cookies, e.g. with spoofed addresses, than the
+    // association limit: each one is answered with a HelloVerifyRequest only
+    let mut reply = [0; 1500];
+    for _ in 0..1100 {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        socket.send_to(&client_hello, addr).unwrap();
+        let len = socket.recv(&mut reply).unwrap();
+        // handshake record with a HelloVerifyRequest
+        assert_eq!((reply[0], reply[13]), (22, 3));
+        assert!(len < client_hello.len());
+    }
+
+    // no association has been kept for them
+    let receiver_fingerprint = Fingerprint::of(&receiver_cert, AuthProtocol::Sha256).unwrap();
+    let config = TlsConfig::new()
+        .with_certificate(agent_cert, agent_key)
+        .with_server_fingerprint(receiver_fingerprint);
+    let transport = DtlsTransport::connect(addr, &config, Some(Duration::from_secs(2))).unwrap();
+    let mut sess = SessionBuilder::v3(Security::tsm(b"alice.example.com"))
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 0))
+        .build_sync_with(transport);
+    let link_up = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 4]);
+    sess.trap(&link_up, 100, &[]).unwrap();
+    let trap = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(trap.community, b"alice.example.com");
+    shutdown.shutdown();
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+async fn async_inform() {
+    use crate::{RetryPolicy, SessionBuilder};
+
+    let (addr, shutdown, notifications) = spawn_trap_listener();
+    let cold_start = oid(&[1, 3, 6, 1, 6, 3, 1, 1, 5, 1]);
+    let mut sess = SessionBuilder::v2c(b"public")
+        .with_retry_policy(RetryPolicy::new(Duration::from_secs(2), 1))
+        .build_async(addr)
+        .await
+        .unwrap();
+    let response = sess.inform(&cold_start, 0, &[]).await.unwrap();
+    assert_eq!(response.message_type, MessageType::Response);
+    assert_eq!(response.varbinds.count(), 2);
+    let inform = notifications.recv_timeout(Duration::from_secs(2)).unwrap();
+    assert_eq!(inform.message_type, MessageType::InformRequest);
+    shutdown.shutdown();
+}
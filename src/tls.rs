@@ -0,0 +1,1221 @@
+//! SNMPv3 over TLS and DTLS (RFC 6353) with the Transport Security Model (RFC 5591).
+//!
+//! Messages are framed over TLS connections like SNMP over TCP, over DTLS each message is sent in
+//! a record of its own, which limits messages to 16384 bytes. The agent authenticates the
+//! manager by its certificate and derives the security name from it, e.g. with a
+//! [`CertificateMap`], so the session uses [`Security::tsm`] with the security name the client
+//! certificate maps to. The manager authenticates the agent by its certificate fingerprint or the
+//! CA certificates. Notifications are received over DTLS by a [`DtlsTrapListener`].
+//!
+//! [`Security::tsm`]: crate::v3::Security::tsm
+//! [`DtlsTrapListener`]: crate::DtlsTrapListener
+
+use std::{
+    collections::VecDeque,
+    ffi::{c_int, c_void},
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket},
+    slice,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+    vec,
+};
+
+use foreign_types::ForeignTypeRef;
+use openssl::{
+    error::ErrorStack,
+    ex_data::Index,
+    hash::MessageDigest,
+    memcmp,
+    nid::Nid,
+    pkey::{PKey, PKeyRef, Private},
+    rand::rand_bytes,
+    sign::Signer,
+    ssl::{
+        self, ErrorCode, Ssl, SslConnector, SslContext, SslMethod, SslOptions, SslRef, SslSession,
+        SslSessionCacheMode, SslStream, SslVerifyMode, SslVersion,
+    },
+    stack::{Stack, StackRef},
+    x509::{
+        store::X509StoreBuilder, verify::X509VerifyFlags, GeneralNameRef, X509PurposeId, X509Ref,
+        X509StoreContext, X509StoreContextRef, X509,
+    },
+};
+
+#[cfg(feature = "tokio")]
+use tokio::{io::Interest, time};
+
+use crate::{
+    socket::remaining,
+    tcp::{self, Connection},
+    transport::no_address,
+    v3::{AuthErrorKind, AuthProtocol},
+    Error, Result, Transport,
+};
+#[cfg(feature = "tokio")]
+use crate::{AsyncTransport, BoxFuture};
+
+/// Security names are limited to 32 characters (RFC 3411 SnmpAdminString).
+const MAX_SECURITY_NAME_LEN: usize = 32;
+
+/// Initial DTLS retransmission timeout, the handshake is polled at this interval to let OpenSSL
+/// retransmit lost flights.
+const DTLS_TIMER: Duration = Duration::from_secs(1);
+
+/// Certificate fingerprint (RFC 6353 SnmpTLSFingerprint): the digest of the DER-encoded
+/// certificate and the hash algorithm it has been computed with.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    algorithm: AuthProtocol,
+    digest: Vec<u8>,
+}
+
+impl Fingerprint {
+    pub fn new(algorithm: AuthProtocol, digest: &[u8]) -> Self {
+        Self {
+            algorithm,
+            digest: digest.to_vec(),
+        }
+    }
+
+    /// Computes the fingerprint of the certificate.
+    pub fn of(certificate: &X509Ref, algorithm: AuthProtocol) -> Result<Self> {
+        let digest = certificate.digest(algorithm.digest())?;
+        Ok(Self::new(algorithm, &digest))
+    }
+
+    /// Parses the SnmpTLSFingerprint encoding: the TLS hash algorithm identifier (RFC 5246)
+    /// followed by the digest.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (&id, digest) = bytes.split_first().ok_or(Error::ValueOutOfRange)?;
+        let algorithm = match id {
+            1 => AuthProtocol::Md5,
+            2 => AuthProtocol::Sha1,
+            3 => AuthProtocol::Sha224,
+            4 => AuthProtocol::Sha256,
+            5 => AuthProtocol::Sha384,
+            6 => AuthProtocol::Sha512,
+            _ => return Err(Error::ValueOutOfRange),
+        };
+        if digest.len() != algorithm.digest().size() {
+            return Err(Error::ValueOutOfRange);
+        }
+        Ok(Self::new(algorithm, digest))
+    }
+
+    /// The SnmpTLSFingerprint encoding, see [`Fingerprint::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let id = match self.algorithm {
+            AuthProtocol::Md5 => 1,
+            AuthProtocol::Sha1 => 2,
+            AuthProtocol::Sha224 => 3,
+            AuthProtocol::Sha256 => 4,
+            AuthProtocol::Sha384 => 5,
+            AuthProtocol::Sha512 => 6,
+        };
+        let mut bytes = Vec::with_capacity(self.digest.len() + 1);
+        bytes.push(id);
+        bytes.extend_from_slice(&self.digest);
+        bytes
+    }
+
+    pub fn algorithm(&self) -> AuthProtocol {
+        self.algorithm
+    }
+
+    pub fn digest(&self) -> &[u8] {
+        &self.digest
+    }
+
+    /// Whether the fingerprint is the one of the certificate.
+    pub fn matches(&self, certificate: &X509Ref) -> bool {
+        certificate
+            .digest(self.algorithm.digest())
+            .map_or(false, |digest| *digest == *self.digest)
+    }
+}
+
+/// How the security name is derived from a certificate (RFC 6353 snmpTlstmCertToTSNMapType).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecurityNameMapping {
+    /// The given security name.
+    Specified(Vec<u8>),
+    /// The first rfc822Name subjectAltName, with the host part in lowercase.
+    SanRfc822Name,
+    /// The first dNSName subjectAltName, in lowercase.
+    SanDnsName,
+    /// The first iPAddress subjectAltName, IPv4 addresses in the dotted decimal notation and IPv6
+    /// ones as 32 lowercase hexadecimal digits.
+    SanIpAddress,
+    /// The first rfc822Name, dNSName or iPAddress subjectAltName, mapped as above.
+    SanAny,
+    /// The subject common name.
+    CommonName,
+}
+
+impl SecurityNameMapping {
+    fn security_name(&self, certificate: &X509Ref) -> Option<Vec<u8>> {
+        let name = match self {
+            SecurityNameMapping::Specified(name) => name.clone(),
+            SecurityNameMapping::CommonName => certificate
+                .subject_name()
+                .entries_by_nid(Nid::COMMONNAME)
+                .next()?
+                .data()
+                .as_slice()
+                .to_vec(),
+            _ => certificate
+                .subject_alt_names()?
+                .iter()
+                .find_map(|name| self.alt_name(name))?,
+        };
+        (!name.is_empty() && name.len() <= MAX_SECURITY_NAME_LEN).then_some(name)
+    }
+
+    fn alt_name(&self, name: &GeneralNameRef) -> Option<Vec<u8>> {
+        let any = *self == SecurityNameMapping::SanAny;
+        if any || *self == SecurityNameMapping::SanRfc822Name {
+            if let Some(email) = name.email() {
+                let (local, host) = email.rsplit_once('@')?;
+                return Some(format!("{}@{}", local, host.to_lowercase()).into_bytes());
+            }
+        }
+        if any || *self == SecurityNameMapping::SanDnsName {
+            if let Some(dns_name) = name.dnsname() {
+                return Some(dns_name.to_lowercase().into_bytes());
+            }
+        }
+        if any || *self == SecurityNameMapping::SanIpAddress {
+            match name.ipaddress()? {
+                [a, b, c, d] => return Some(format!("{}.{}.{}.{}", a, b, c, d).into_bytes()),
+                addr if addr.len() == 16 => {
+                    let hex = addr.iter().fold(String::new(), |mut s, b| {
+                        let _ = write!(s, "{:02x}", b);
+                        s
+                    });
+                    return Some(hex.into_bytes());
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+/// Maps certificates to security names (RFC 6353 snmpTlstmCertToTSNTable), used by agents and
+/// notification receivers to identify the peers authenticated by TLS.
+///
+/// The entries are checked in order. An entry applies when its fingerprint is the one of the
+/// presented certificate, or of a CA certificate of its chain it is verified with. The security
+/// name is then derived from the presented certificate. Entries which fail to derive a name are
+/// skipped.
+#[derive(Debug, Clone, Default)]
+pub struct CertificateMap {
+    entries: Vec<(Fingerprint, SecurityNameMapping)>,
+}
+
+impl CertificateMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_entry(mut self, fingerprint: Fingerprint, mapping: SecurityNameMapping) -> Self {
+        self.entries.push((fingerprint, mapping));
+        self
+    }
+
+    /// Derives the security name from the certificate chain, presented certificate first.
+    pub fn security_name(&self, chain: &[X509]) -> Option<Vec<u8>> {
+        let certificate = chain.first()?;
+        let mut stack = Stack::new().ok()?;
+        for c in chain {
+            stack.push(c.clone()).ok()?;
+        }
+        self.entries
+            .iter()
+            .filter(|(fingerprint, _)| {
+                let purpose = X509PurposeId::SSL_CLIENT;
+                is_pinned(slice::from_ref(fingerprint), certificate, &stack, purpose)
+                    .unwrap_or(false)
+            })
+            .find_map(|(_, mapping)| mapping.security_name(certificate))
+    }
+
+    /// Derives the security name of the peer of a verified TLS connection.
+    pub fn peer_security_name(&self, ssl: &SslRef) -> Option<Vec<u8>> {
+        self.security_name(&peer_certificates(ssl))
+    }
+}
+
+/// The peer certificate chain, presented certificate first.
+fn peer_certificates(ssl: &SslRef) -> Vec<X509> {
+    let Some(certificate) = ssl.peer_certificate() else {
+        return Vec::new();
+    };
+    let mut chain = vec![certificate];
+    // the chain includes the presented certificate on the client side only
+    for c in ssl.peer_cert_chain().into_iter().flatten() {
+        if *c != chain[0] {
+            chain.push(c.to_owned());
+        }
+    }
+    chain
+}
+
+/// TLS settings of a manager: the client certificate and how the agent certificate is verified.
+///
+/// The agent certificate is accepted if it matches a server fingerprint (RFC 6353
+/// snmpTlstmAddrServerFingerprint), or if its chain is verified up to a CA certificate matching
+/// one. When no fingerprints are set, it must be verified with the CA certificates and have the
+/// server name, connecting fails if there is none.
+#[derive(Clone, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct TlsConfig {
+    certificate: Option<(X509, PKey<Private>)>,
+    ca_certificates: Vec<X509>,
+    server_fingerprints: Vec<Fingerprint>,
+    server_name: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Client certificate and its private key, the identity of the manager.
+    pub fn with_certificate(mut self, certificate: X509, key: PKey<Private>) -> Self {
+        self.certificate = Some((certificate, key));
+        self
+    }
+
+    /// Trusts the CA certificate in addition to the system ones.
+    pub fn with_ca_certificate(mut self, certificate: X509) -> Self {
+        self.ca_certificates.push(certificate);
+        self
+    }
+
+    /// Accepts agent certificates with the fingerprint, or verified up to the CA certificate with
+    /// it.
+    pub fn with_server_fingerprint(mut self, fingerprint: Fingerprint) -> Self {
+        self.server_fingerprints.push(fingerprint);
+        self
+    }
+
+    /// Server name, sent to the agent and checked against its certificate.
+    pub fn with_server_name(mut self, server_name: &str) -> Self {
+        self.server_name = Some(server_name.to_owned());
+        self
+    }
+
+    fn connector(&self, method: SslMethod, min_version: SslVersion) -> Result<Connector> {
+        let mut builder = SslConnector::builder(method)?;
+        builder.set_min_proto_version(Some(min_version))?;
+        if let Some((ref certificate, ref key)) = self.certificate {
+            builder.set_certificate(certificate)?;
+            builder.set_private_key(key)?;
+            builder.check_private_key()?;
+        }
+        for certificate in &self.ca_certificates {
+            builder.cert_store_mut().add_cert(certificate.clone())?;
+        }
+        let pinned = !self.server_fingerprints.is_empty();
+        if pinned {
+            let fingerprints = self.server_fingerprints.clone();
+            builder.set_verify_callback(SslVerifyMode::PEER, move |_, ctx| {
+                verify_pinned(&fingerprints, X509PurposeId::SSL_SERVER, ctx)
+            });
+        } else if self.server_name.is_none() {
+            // any certificate issued by a trusted CA would be accepted as the agent
+            return Err(Error::AuthFailure(AuthErrorKind::SecurityNotProvided));
+        }
+        let session = Arc::new(Mutex::new(None));
+        builder.set_session_cache_mode(SslSessionCacheMode::CLIENT);
+        let last_session = session.clone();
+        builder.set_new_session_callback(move |_, session| {
+            *last_session.lock().unwrap_or_else(PoisonError::into_inner) = Some(session);
+        });
+        Ok(Connector {
+            connector: builder.build(),
+            server_name: self.server_name.clone(),
+            pinned,
+            session,
+        })
+    }
+}
+
+/// Verifies a peer certificate chain against pinned fingerprints, see [`is_pinned`].
+fn verify_pinned(
+    fingerprints: &[Fingerprint],
+    purpose: X509PurposeId,
+    ctx: &X509StoreContextRef,
+) -> bool {
+    let Some(chain) = ctx.chain() else {
+        return false;
+    };
+    let Some(certificate) = chain.iter().next() else {
+        return false;
+    };
+    is_pinned(fingerprints, certificate, chain, purpose).unwrap_or(false)
+}
+
+/// Whether the certificate has one of the fingerprints, or is verified with the chain up to a CA
+/// certificate with one of them, used as the only trust anchor.
+fn is_pinned(
+    fingerprints: &[Fingerprint],
+    certificate: &X509Ref,
+    chain: &StackRef<X509>,
+    purpose: X509PurposeId,
+) -> Result<bool> {
+    if fingerprints.iter().any(|f| f.matches(certificate)) {
+        return Ok(true);
+    }
+    let mut store = X509StoreBuilder::new()?;
+    store.set_purpose(purpose)?;
+    // the CA may be an intermediate one
+    store.set_flags(X509VerifyFlags::PARTIAL_CHAIN)?;
+    let mut anchored = false;
+    for ca in chain {
+        if fingerprints.iter().any(|f| f.matches(ca)) {
+            store.add_cert(ca.to_owned())?;
+            anchored = true;
+        }
+    }
+    if !anchored {
+        return Ok(false);
+    }
+    let store = store.build();
+    let mut context = X509StoreContext::new()?;
+    Ok(context.init(&store, certificate, chain, X509StoreContextRef::verify_cert)?)
+}
+
+/// Built TLS settings, kept by the transports to connect again. Connections are established
+/// resuming the last session.
+struct Connector {
+    connector: SslConnector,
+    server_name: Option<String>,
+    pinned: bool,
+    session: Arc<Mutex<Option<SslSession>>>,
+}
+
+impl Connector {
+    fn ssl(&self) -> io::Result<Ssl> {
+        let mut config = self.connector.configure().map_err(other)?;
+        config.set_use_server_name_indication(self.server_name.is_some());
+        config.set_verify_hostname(self.server_name.is_some() && !self.pinned);
+        let mut ssl = config
+            .into_ssl(self.server_name.as_deref().unwrap_or_default())
+            .map_err(other)?;
+        if let Some(ref session) = *self.session.lock().unwrap_or_else(PoisonError::into_inner) {
+            // SAFETY: the session has been established with the context of the connector
+            unsafe { ssl.set_session(session) }.map_err(other)?;
+        }
+        Ok(ssl)
+    }
+
+    /// Forgets the session after a failed handshake, in case the peer does not accept it.
+    fn failed(&self, e: io::Error) -> io::Error {
+        *self.session.lock().unwrap_or_else(PoisonError::into_inner) = None;
+        e
+    }
+
+    fn open(
+        &self,
+        peer: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> io::Result<SslStream<TcpStream>> {
+        let stream = if let Some(timeout) = timeout {
+            TcpStream::connect_timeout(&peer, timeout)?
+        } else {
+            TcpStream::connect(peer)?
+        };
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(timeout)?;
+        stream.set_write_timeout(timeout)?;
+        let mut stream = SslStream::new(self.ssl()?, stream).map_err(other)?;
+        stream.connect().map_err(|e| self.failed(tls_error(e)))?;
+        Ok(stream)
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn open_async(&self, peer: SocketAddr) -> io::Result<SslStream<NonBlocking>> {
+        let stream = tokio::net::TcpStream::connect(peer).await?;
+        stream.set_nodelay(true)?;
+        let mut stream = SslStream::new(self.ssl()?, NonBlocking(stream)).map_err(other)?;
+        loop {
+            match stream.connect() {
+                Ok(()) => return Ok(stream),
+                Err(e) => {
+                    let interest = interest(e).map_err(|e| self.failed(e))?;
+                    stream.get_ref().0.ready(interest).await?;
+                }
+            }
+        }
+    }
+
+    fn open_dtls(
+        &self,
+        peer: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> io::Result<SslStream<Datagram>> {
+        let socket = UdpSocket::bind(unspecified(peer))?;
+        socket.connect(peer)?;
+        socket.set_write_timeout(timeout)?;
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut stream = SslStream::new(self.ssl()?, Datagram(socket)).map_err(other)?;
+        loop {
+            let timer = remaining(deadline)?.map_or(DTLS_TIMER, |left| left.min(DTLS_TIMER));
+            stream.get_ref().0.set_read_timeout(Some(timer))?;
+            match stream.connect() {
+                Ok(()) => return Ok(stream),
+                Err(e) if e.code() == ErrorCode::WANT_READ => {}
+                Err(e) => return Err(self.failed(tls_error(e))),
+            }
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    async fn open_dtls_async(&self, peer: SocketAddr) -> io::Result<SslStream<AsyncDatagram>> {
+        let socket = tokio::net::UdpSocket::bind(unspecified(peer)).await?;
+        socket.connect(peer).await?;
+        let mut stream = SslStream::new(self.ssl()?, AsyncDatagram(socket)).map_err(other)?;
+        loop {
+            match stream.connect() {
+                Ok(()) => return Ok(stream),
+                Err(e) => {
+                    let interest = interest(e).map_err(|e| self.failed(e))?;
+                    // wake up when the DTLS timer expires to retransmit
+                    let _ = time::timeout(DTLS_TIMER, stream.get_ref().0.ready(interest)).await;
+                }
+            }
+        }
+    }
+}
+
+fn unspecified(peer: SocketAddr) -> SocketAddr {
+    match peer {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+fn other(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Fails if only part of the message has been written, messages are sent in a DTLS record of
+/// their own.
+fn whole_record(len: usize, msg: &[u8]) -> io::Result<()> {
+    if len < msg.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Message too large for a DTLS record",
+        ));
+    }
+    Ok(())
+}
+
+/// Readiness the TLS engine waits for to go on after the error, fails on other errors.
+#[cfg(feature = "tokio")]
+fn interest(e: ssl::Error) -> io::Result<Interest> {
+    match e.code() {
+        ErrorCode::WANT_READ => Ok(Interest::READABLE),
+        ErrorCode::WANT_WRITE => Ok(Interest::WRITABLE),
+        _ => Err(tls_error(e)),
+    }
+}
+
+fn tls_error(e: ssl::Error) -> io::Error {
+    if e.code() == ErrorCode::ZERO_RETURN {
+        return io::ErrorKind::UnexpectedEof.into();
+    }
+    e.into_io_error().unwrap_or_else(other)
+}
+
+impl Connection for SslStream<TcpStream> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+}
+
+/// SNMPv3 over TLS transport of a [`SyncSession`], see the [module](self) documentation.
+///
+/// A broken connection is established again once per request, resuming the TLS session. The
+/// default agent port is 10161.
+///
+/// [`SyncSession`]: crate::SyncSession
+#[allow(clippy::module_name_repetitions)]
+pub struct TlsTransport {
+    stream: tcp::Stream<SslStream<TcpStream>>,
+    connector: Connector,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+    can_reconnect: bool,
+}
+
+impl TlsTransport {
+    /// Connects to the first reachable address of the agent. The timeout limits connection
+    /// establishment, the handshake and sending.
+    pub fn connect<A: ToSocketAddrs>(
+        destination: A,
+        config: &TlsConfig,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        let connector = config
+            .connector(SslMethod::tls_client(), SslVersion::TLS1_2)
+            .map_err(other)?;
+        let mut error = None;
+        for peer in destination.to_socket_addrs()? {
+            match connector.open(peer, timeout) {
+                Ok(stream) => {
+                    return Ok(Self {
+                        stream: tcp::Stream::new(stream),
+                        connector,
+                        peer,
+                        timeout,
+                        can_reconnect: false,
+                    })
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(no_address))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.get_ref().get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// The agent certificate chain, presented certificate first.
+    pub fn peer_certificates(&self) -> Vec<X509> {
+        peer_certificates(self.stream.get_ref().ssl())
+    }
+
+    /// Whether the connection has resumed the session of the previous one.
+    pub fn session_reused(&self) -> bool {
+        self.stream.get_ref().ssl().session_reused()
+    }
+}
+
+impl Transport for TlsTransport {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.stream.send(msg)
+    }
+
+    fn recv(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        self.stream.recv(buf, deadline)
+    }
+
+    fn is_reliable(&self) -> bool {
+        true
+    }
+
+    fn begin(&mut self) {
+        self.can_reconnect = true;
+    }
+
+    fn reconnect(&mut self) -> bool {
+        if !std::mem::take(&mut self.can_reconnect) {
+            return false;
+        }
+        let Ok(stream) = self.connector.open(self.peer, self.timeout) else {
+            return false;
+        };
+        self.stream = tcp::Stream::new(stream);
+        true
+    }
+}
+
+/// Nonblocking TCP stream under an asynchronous TLS connection, the TLS engine reads and writes
+/// it until it would block and the transport waits for the readiness it needs then.
+#[cfg(feature = "tokio")]
+struct NonBlocking(tokio::net::TcpStream);
+
+#[cfg(feature = "tokio")]
+impl Read for NonBlocking {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.try_read(buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Write for NonBlocking {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.try_write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// SNMPv3 over TLS transport of an [`AsyncSession`], see [`TlsTransport`].
+///
+/// Sending and receiving are cancellation-safe, like over TCP.
+///
+/// [`AsyncSession`]: crate::AsyncSession
+#[cfg(feature = "tokio")]
+#[allow(clippy::module_name_repetitions)]
+pub struct AsyncTlsTransport {
+    stream: SslStream<NonBlocking>,
+    unsent: Vec<u8>,
+    framer: tcp::Framer,
+    connector: Connector,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+    can_reconnect: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncTlsTransport {
+    /// Connects to the first reachable address of the agent. The timeout limits connection
+    /// establishment and the handshake.
+    pub async fn connect<A: tokio::net::ToSocketAddrs>(
+        destination: A,
+        config: &TlsConfig,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        let connector = config
+            .connector(SslMethod::tls_client(), SslVersion::TLS1_2)
+            .map_err(other)?;
+        let mut error = None;
+        for peer in tokio::net::lookup_host(destination).await? {
+            match open_async(&connector, peer, timeout).await {
+                Ok(stream) => {
+                    return Ok(Self {
+                        stream,
+                        unsent: Vec::new(),
+                        framer: tcp::Framer::default(),
+                        connector,
+                        peer,
+                        timeout,
+                        can_reconnect: false,
+                    })
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(no_address))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.get_ref().0.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// The agent certificate chain, presented certificate first.
+    pub fn peer_certificates(&self) -> Vec<X509> {
+        peer_certificates(self.stream.ssl())
+    }
+
+    /// Whether the connection has resumed the session of the previous one.
+    pub fn session_reused(&self) -> bool {
+        self.stream.ssl().session_reused()
+    }
+
+    async fn send_unsent(&mut self) -> io::Result<()> {
+        while !self.unsent.is_empty() {
+            match self.stream.ssl_write(&self.unsent) {
+                Ok(len) => {
+                    self.unsent.drain(..len);
+                }
+                Err(e) => {
+                    self.stream.get_ref().0.ready(interest(e)?).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn recv_message(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0; tcp::READ_CHUNK];
+        loop {
+            if let Some(len) = self.framer.next_message(out)? {
+                return Ok(len);
+            }
+            match self.stream.ssl_read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => self.framer.push(&chunk[..len]),
+                Err(e) => {
+                    self.stream.get_ref().0.ready(interest(e)?).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn open_async(
+    connector: &Connector,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+) -> io::Result<SslStream<NonBlocking>> {
+    let opening = connector.open_async(peer);
+    if let Some(timeout) = timeout {
+        time::timeout(timeout, opening)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+    } else {
+        opening.await
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncTransport for AsyncTlsTransport {
+    fn send<'a>(&'a mut self, msg: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        self.unsent.extend_from_slice(msg);
+        Box::pin(self.send_unsent())
+    }
+
+    fn recv<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.recv_message(buf))
+    }
+
+    fn is_reliable(&self) -> bool {
+        true
+    }
+
+    fn begin(&mut self) -> BoxFuture<'_, ()> {
+        self.can_reconnect = true;
+        Box::pin(async {})
+    }
+
+    fn reconnect(&mut self) -> BoxFuture<'_, bool> {
+        Box::pin(async {
+            if !std::mem::take(&mut self.can_reconnect) {
+                return false;
+            }
+            let Ok(stream) = open_async(&self.connector, self.peer, self.timeout).await else {
+                return false;
+            };
+            self.stream = stream;
+            self.unsent.clear();
+            self.framer = tcp::Framer::default();
+            true
+        })
+    }
+}
+
+/// Connected UDP socket under a DTLS association, each read and write is a datagram.
+struct Datagram(UdpSocket);
+
+impl Read for Datagram {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf)
+    }
+}
+
+impl Write for Datagram {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Datagrams of a DTLS association of a notification receiver, which shares its socket with the
+/// other associations: the received datagrams are queued until the association reads them, and
+/// the written ones until they are sent.
+#[derive(Default)]
+pub(crate) struct Channel {
+    incoming: VecDeque<Vec<u8>>,
+    outgoing: Vec<Vec<u8>>,
+}
+
+impl Channel {
+    pub(crate) fn push(&mut self, datagram: &[u8]) {
+        self.incoming.push_back(datagram.to_vec());
+    }
+
+    pub(crate) fn drain(&mut self) -> vec::Drain<'_, Vec<u8>> {
+        self.outgoing.drain(..)
+    }
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let datagram = self.incoming.pop_front().ok_or(io::ErrorKind::WouldBlock)?;
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok(len)
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// not bound by the openssl crate, the BIO_ADDR is opaque
+extern "C" {
+    fn DTLSv1_listen(ssl: *mut c_void, client: *mut c_void) -> c_int;
+    fn BIO_ADDR_new() -> *mut c_void;
+    fn BIO_ADDR_free(addr: *mut c_void);
+}
+
+/// Computes the cookie of the agent address: the agents which return it receive the datagrams
+/// sent to the address, i.e. do not spoof it (RFC 6347 section 4.2.1).
+fn cookie(secret: &[u8], peer: SocketAddr) -> std::result::Result<Vec<u8>, ErrorStack> {
+    let key = PKey::hmac(secret)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(peer.to_string().as_bytes())?;
+    signer.sign_to_vec()
+}
+
+/// DTLS context of a notification receiver with its certificate. The agents must present a
+/// certificate the map applies to, and return the cookie of their address before any state is
+/// kept for them.
+pub(crate) struct DtlsServer {
+    context: SslContext,
+    /// Agent address of an association, which the cookie is computed from.
+    peer: Index<Ssl, SocketAddr>,
+}
+
+impl DtlsServer {
+    pub(crate) fn new(
+        certificate: &X509Ref,
+        key: &PKeyRef<Private>,
+        map: &CertificateMap,
+    ) -> Result<Self> {
+        let mut builder = SslContext::builder(SslMethod::dtls())?;
+        builder.set_min_proto_version(Some(SslVersion::DTLS1_2))?;
+        builder.set_certificate(certificate)?;
+        builder.set_private_key(key)?;
+        builder.check_private_key()?;
+        builder.set_session_id_context(b"snmp")?;
+        let fingerprints = map
+            .entries
+            .iter()
+            .map(|(fingerprint, _)| fingerprint.clone())
+            .collect::<Vec<_>>();
+        builder.set_verify_callback(
+            SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            move |_, ctx| verify_pinned(&fingerprints, X509PurposeId::SSL_CLIENT, ctx),
+        );
+        let peer = Ssl::new_ex_index()?;
+        let mut secret = [0; 32];
+        rand_bytes(&mut secret)?;
+        builder.set_options(SslOptions::COOKIE_EXCHANGE);
+        builder.set_cookie_generate_cb(move |ssl, out| {
+            let addr = *ssl.ex_data(peer).ok_or_else(ErrorStack::get)?;
+            let cookie = cookie(&secret, addr)?;
+            let len = cookie.len().min(out.len());
+            out[..len].copy_from_slice(&cookie[..len]);
+            Ok(len)
+        });
+        builder.set_cookie_verify_cb(move |ssl, received| {
+            ssl.ex_data(peer)
+                .and_then(|&addr| cookie(&secret, addr).ok())
+                .map_or(false, |cookie| {
+                    cookie.len() == received.len() && memcmp::eq(&cookie, received)
+                })
+        });
+        Ok(Self {
+            context: builder.build(),
+            peer,
+        })
+    }
+
+    /// Answers the ClientHello of a new agent with a HelloVerifyRequest carrying the cookie of
+    /// its address, without keeping any state. Returns the association and whether the agent
+    /// has returned the cookie: the handshake then goes on with the buffered ClientHello,
+    /// otherwise the association only holds the HelloVerifyRequest to send.
+    pub(crate) fn listen(
+        &self,
+        addr: SocketAddr,
+        datagram: &[u8],
+    ) -> Result<(SslStream<Channel>, bool)> {
+        let mut ssl = Ssl::new(&self.context)?;
+        ssl.set_ex_data(self.peer, addr);
+        let mut stream = SslStream::new(ssl, Channel::default())?;
+        stream.get_mut().push(datagram);
+        let client = unsafe { BIO_ADDR_new() };
+        if client.is_null() {
+            return Err(ErrorStack::get().into());
+        }
+        let ret = unsafe {
+            let ret = DTLSv1_listen(stream.ssl().as_ptr().cast(), client);
+            BIO_ADDR_free(client);
+            ret
+        };
+        if ret < 0 {
+            // not a ClientHello, dropped
+            let _ = ErrorStack::get();
+        }
+        Ok((stream, ret > 0))
+    }
+}
+
+/// SNMPv3 over DTLS transport of a [`SyncSession`], see the [module](self) documentation.
+///
+/// Requests are retransmitted like over UDP. When a request gets no response, e.g. because the
+/// agent has lost the association after a restart, the handshake is done again once per request,
+/// resuming the DTLS session. The default agent port is 10161.
+///
+/// [`SyncSession`]: crate::SyncSession
+#[allow(clippy::module_name_repetitions)]
+pub struct DtlsTransport {
+    stream: SslStream<Datagram>,
+    connector: Connector,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+    can_reconnect: bool,
+}
+
+impl DtlsTransport {
+    /// Establishes a DTLS association with the first reachable address of the agent. The timeout
+    /// limits the handshake and sending.
+    pub fn connect<A: ToSocketAddrs>(
+        destination: A,
+        config: &TlsConfig,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        let connector = config
+            .connector(SslMethod::dtls(), SslVersion::DTLS1_2)
+            .map_err(other)?;
+        let mut error = None;
+        for peer in destination.to_socket_addrs()? {
+            match connector.open_dtls(peer, timeout) {
+                Ok(stream) => {
+                    return Ok(Self {
+                        stream,
+                        connector,
+                        peer,
+                        timeout,
+                        can_reconnect: false,
+                    })
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(no_address))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.get_ref().0.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// The agent certificate chain, presented certificate first.
+    pub fn peer_certificates(&self) -> Vec<X509> {
+        peer_certificates(self.stream.ssl())
+    }
+
+    /// Whether the association has resumed the session of the previous one.
+    pub fn session_reused(&self) -> bool {
+        self.stream.ssl().session_reused()
+    }
+}
+
+impl Transport for DtlsTransport {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        let len = self.stream.write(msg)?;
+        whole_record(len, msg)
+    }
+
+    fn recv(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        self.stream
+            .get_ref()
+            .0
+            .set_read_timeout(remaining(deadline)?)?;
+        match self.stream.read(buf)? {
+            0 => Err(io::ErrorKind::UnexpectedEof.into()),
+            len => Ok(len),
+        }
+    }
+
+    fn begin(&mut self) {
+        self.can_reconnect = true;
+    }
+
+    fn reconnect(&mut self) -> bool {
+        if !std::mem::take(&mut self.can_reconnect) {
+            return false;
+        }
+        let Ok(stream) = self.connector.open_dtls(self.peer, self.timeout) else {
+            return false;
+        };
+        self.stream = stream;
+        true
+    }
+}
+
+/// Nonblocking UDP socket under an asynchronous DTLS association, see [`NonBlocking`].
+#[cfg(feature = "tokio")]
+struct AsyncDatagram(tokio::net::UdpSocket);
+
+#[cfg(feature = "tokio")]
+impl Read for AsyncDatagram {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.try_recv(buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Write for AsyncDatagram {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.try_send(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// SNMPv3 over DTLS transport of an [`AsyncSession`], see [`DtlsTransport`].
+///
+/// [`AsyncSession`]: crate::AsyncSession
+#[cfg(feature = "tokio")]
+#[allow(clippy::module_name_repetitions)]
+pub struct AsyncDtlsTransport {
+    stream: SslStream<AsyncDatagram>,
+    connector: Connector,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+    can_reconnect: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncDtlsTransport {
+    /// Establishes a DTLS association with the first reachable address of the agent. The timeout
+    /// limits the handshake.
+    pub async fn connect<A: tokio::net::ToSocketAddrs>(
+        destination: A,
+        config: &TlsConfig,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        let connector = config
+            .connector(SslMethod::dtls(), SslVersion::DTLS1_2)
+            .map_err(other)?;
+        let mut error = None;
+        for peer in tokio::net::lookup_host(destination).await? {
+            match open_dtls_async(&connector, peer, timeout).await {
+                Ok(stream) => {
+                    return Ok(Self {
+                        stream,
+                        connector,
+                        peer,
+                        timeout,
+                        can_reconnect: false,
+                    })
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(no_address))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.get_ref().0.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// The agent certificate chain, presented certificate first.
+    pub fn peer_certificates(&self) -> Vec<X509> {
+        peer_certificates(self.stream.ssl())
+    }
+
+    /// Whether the association has resumed the session of the previous one.
+    pub fn session_reused(&self) -> bool {
+        self.stream.ssl().session_reused()
+    }
+
+    async fn send_record(&mut self, msg: &[u8]) -> io::Result<()> {
+        loop {
+            match self.stream.ssl_write(msg) {
+                Ok(len) => return whole_record(len, msg),
+                Err(e) => {
+                    self.stream.get_ref().0.ready(interest(e)?).await?;
+                }
+            }
+        }
+    }
+
+    /// Receives the next record, cancellation-safe as records are read whole.
+    async fn recv_record(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.stream.ssl_read(buf) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(len) => return Ok(len),
+                Err(e) => {
+                    self.stream.get_ref().0.ready(interest(e)?).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn open_dtls_async(
+    connector: &Connector,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+) -> io::Result<SslStream<AsyncDatagram>> {
+    let opening = connector.open_dtls_async(peer);
+    if let Some(timeout) = timeout {
+        time::timeout(timeout, opening)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+    } else {
+        opening.await
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncTransport for AsyncDtlsTransport {
+    fn send<'a>(&'a mut self, msg: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(self.send_record(msg))
+    }
+
+    fn recv<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.recv_record(buf))
+    }
+
+    fn begin(&mut self) -> BoxFuture<'_, ()> {
+        self.can_reconnect = true;
+        Box::pin(async {})
+    }
+
+    fn reconnect(&mut self) -> BoxFuture<'_, bool> {
+        Box::pin(async {
+            if !std::mem::take(&mut self.can_reconnect) {
+                return false;
+            }
+            let Ok(stream) = open_dtls_async(&self.connector, self.peer, self.timeout).await
+            else {
+                return false;
+            };
+            self.stream = stream;
+            true
+        })
+    }
+}
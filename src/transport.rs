@@ -0,0 +1,341 @@
+use std::{
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "tokio")]
+use std::{future::Future, pin::Pin};
+
+#[cfg(feature = "tokio")]
+use tokio::time;
+
+#[cfg(feature = "tokio")]
+use crate::{peers::AsyncResolve, socket::AsyncSocket};
+use crate::{
+    peers::{Peers, Resolve},
+    socket::{Socket, SocketConfig},
+};
+
+/// Future returned by [`AsyncTransport`] methods.
+#[cfg(feature = "tokio")]
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Message transport of a [`SyncSession`], which carries encoded SNMP messages to an agent and
+/// back, e.g. over UDP, TCP, a Unix socket, an SSH tunnel or an in-memory mock.
+///
+/// [`SyncSession`]: crate::SyncSession
+pub trait Transport {
+    /// Sends a whole message.
+    fn send(&mut self, msg: &[u8]) -> io::Result<()>;
+
+    /// Receives the next whole message into `buf`, returns its length. Fails with
+    /// [`io::ErrorKind::TimedOut`] or [`io::ErrorKind::WouldBlock`] when no message arrives
+    /// before the deadline, `None` waits forever.
+    fn recv(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize>;
+
+    /// Whether messages are delivered reliably, e.g. over a stream. Requests are not
+    /// retransmitted over reliable transports. Defaults to `false`.
+    fn is_reliable(&self) -> bool {
+        false
+    }
+
+    /// Called before each request is sent.
+    fn begin(&mut self) {}
+
+    /// Called when a request can not be sent, fails to receive or times out. The transport may
+    /// connect again, e.g. to another agent address, and return `true` to send the request once
+    /// more. Defaults to `false`.
+    fn reconnect(&mut self) -> bool {
+        false
+    }
+}
+
+/// Message transport of an [`AsyncSession`], see [`Transport`].
+///
+/// [`AsyncSession`]: crate::AsyncSession
+#[cfg(feature = "tokio")]
+#[allow(clippy::module_name_repetitions)]
+pub trait AsyncTransport: Send {
+    /// Sends a whole message.
+    fn send<'a>(&'a mut self, msg: &'a [u8]) -> BoxFuture<'a, io::Result<()>>;
+
+    /// Receives the next whole message into `buf`, returns its length. The session drops the
+    /// future when the request times out, so receiving must be cancellation-safe.
+    fn recv<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<usize>>;
+
+    /// Whether messages are delivered reliably, e.g. over a stream. Requests are not
+    /// retransmitted over reliable transports. Defaults to `false`.
+    fn is_reliable(&self) -> bool {
+        false
+    }
+
+    /// Called before each request is sent.
+    fn begin(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(async {})
+    }
+
+    /// Called when a request can not be sent, fails to receive or times out. The transport may
+    /// connect again, e.g. to another agent address, and return `true` to send the request once
+    /// more. Defaults to `false`.
+    fn reconnect(&mut self) -> BoxFuture<'_, bool> {
+        Box::pin(async { false })
+    }
+}
+
+/// UDP or TCP transport, built by [`SessionBuilder`].
+///
+/// Falls back through all resolved agent addresses: when a request fails over the current
+/// address, it is sent to the next one, and the first address to respond becomes the current
+/// one. TCP connections are established again when they break or time out.
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+#[allow(clippy::module_name_repetitions)]
+pub struct NetTransport {
+    socket: Socket,
+    config: SocketConfig,
+    peers: Peers<Resolve>,
+    timeout: Option<Duration>,
+    /// Addresses left to try for the current request, the next one last.
+    fallbacks: Vec<usize>,
+}
+
+impl NetTransport {
+    /// Connects to the first reachable address. The timeout limits connection establishment and
+    /// sending.
+    pub(crate) fn connect(
+        config: SocketConfig,
+        mut peers: Peers<Resolve>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        let mut error = None;
+        for index in 0..peers.len() {
+            match config.connect(peers.addr(index), timeout) {
+                Ok(socket) => {
+                    peers.select(index);
+                    return Ok(Self {
+                        socket,
+                        config,
+                        peers,
+                        timeout,
+                        fallbacks: Vec::new(),
+                    });
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(no_address))
+    }
+
+    /// Local address of the transport socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Agent address the requests are currently sent to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peers.addr(self.peers.current())
+    }
+
+    /// Resolves the destination again when the resolve interval has elapsed.
+    fn refresh(&mut self) {
+        let Some(Ok(addrs)) = self.peers.resolve_due().map(|resolve| resolve()) else {
+            return;
+        };
+        if !self.peers.update(addrs) {
+            for index in 0..self.peers.len() {
+                if self.connect_to(index) {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn connect_to(&mut self, index: usize) -> bool {
+        let Ok(socket) = self.config.connect(self.peers.addr(index), self.timeout) else {
+            return false;
+        };
+        self.socket = socket;
+        self.peers.select(index);
+        true
+    }
+}
+
+impl Transport for NetTransport {
+    fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        self.socket.send(msg)
+    }
+
+    fn recv(&mut self, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+        self.socket.recv(buf, deadline)
+    }
+
+    fn is_reliable(&self) -> bool {
+        self.socket.is_stream()
+    }
+
+    fn begin(&mut self) {
+        self.refresh();
+        self.fallbacks = fallbacks(&self.peers, self.socket.is_stream());
+    }
+
+    fn reconnect(&mut self) -> bool {
+        while let Some(index) = self.fallbacks.pop() {
+            if self.connect_to(index) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Asynchronous UDP or TCP transport, built by [`SessionBuilder`], see [`NetTransport`].
+///
+/// [`SessionBuilder`]: crate::SessionBuilder
+#[cfg(feature = "tokio")]
+#[allow(clippy::module_name_repetitions)]
+pub struct AsyncNetTransport {
+    socket: AsyncSocket,
+    config: SocketConfig,
+    peers: Peers<AsyncResolve>,
+    timeout: Option<Duration>,
+    /// Addresses left to try for the current request, the next one last.
+    fallbacks: Vec<usize>,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncNetTransport {
+    /// Connects to the first reachable address. The timeout limits connection establishment.
+    pub(crate) async fn connect(
+        config: SocketConfig,
+        mut peers: Peers<AsyncResolve>,
+        timeout: Option<Duration>,
+    ) -> io::Result<Self> {
+        let mut error = None;
+        for index in 0..peers.len() {
+            match connect_async(&config, peers.addr(index), timeout).await {
+                Ok(socket) => {
+                    peers.select(index);
+                    return Ok(Self {
+                        socket,
+                        config,
+                        peers,
+                        timeout,
+                        fallbacks: Vec::new(),
+                    });
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(no_address))
+    }
+
+    /// Local address of the transport socket.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Agent address the requests are currently sent to.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peers.addr(self.peers.current())
+    }
+
+    pub(crate) fn into_socket(self) -> AsyncSocket {
+        self.socket
+    }
+
+    /// Resolves the destination again when the resolve interval has elapsed.
+    async fn refresh(&mut self) {
+        let Some(resolving) = self.peers.resolve_due().map(|resolve| resolve()) else {
+            return;
+        };
+        let Ok(addrs) = resolving.await else {
+            return;
+        };
+        if !self.peers.update(addrs) {
+            for index in 0..self.peers.len() {
+                if self.connect_to(index).await {
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn connect_to(&mut self, index: usize) -> bool {
+        let Ok(socket) = connect_async(&self.config, self.peers.addr(index), self.timeout).await
+        else {
+            return false;
+        };
+        self.socket = socket;
+        self.peers.select(index);
+        true
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncTransport for AsyncNetTransport {
+    fn send<'a>(&'a mut self, msg: &'a [u8]) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(self.socket.send(msg))
+    }
+
+    fn recv<'a>(&'a mut self, buf: &'a mut [u8]) -> BoxFuture<'a, io::Result<usize>> {
+        Box::pin(self.socket.recv(buf))
+    }
+
+    fn is_reliable(&self) -> bool {
+        self.socket.is_stream()
+    }
+
+    fn begin(&mut self) -> BoxFuture<'_, ()> {
+        Box::pin(async {
+            self.refresh().await;
+            self.fallbacks = fallbacks(&self.peers, self.socket.is_stream());
+        })
+    }
+
+    fn reconnect(&mut self) -> BoxFuture<'_, bool> {
+        Box::pin(async {
+            while let Some(index) = self.fallbacks.pop() {
+                if self.connect_to(index).await {
+                    return true;
+                }
+            }
+            false
+        })
+    }
+}
+
+#[cfg(feature = "tokio")]
+async fn connect_async(
+    config: &SocketConfig,
+    peer: SocketAddr,
+    timeout: Option<Duration>,
+) -> io::Result<AsyncSocket> {
+    let connecting = config.connect_async(peer);
+    if let Some(timeout) = timeout {
+        time::timeout(timeout, connecting)
+            .await
+            .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+    } else {
+        connecting.await
+    }
+}
+
+/// Addresses to fall back to, in reverse order. A broken connection is established again to the
+/// current address first.
+fn fallbacks<R>(peers: &Peers<R>, stream: bool) -> Vec<usize> {
+    let mut fallbacks = peers.fallbacks().collect::<Vec<_>>();
+    if stream {
+        fallbacks.insert(0, peers.current());
+    }
+    fallbacks.reverse();
+    fallbacks
+}
+
+pub(crate) fn no_address() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "No address found")
+}
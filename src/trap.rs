@@ -0,0 +1,765 @@
+//! Notification receivers: SNMPv1 traps, SNMPv2c/v3 traps and inform requests sent by agents,
+//! over UDP or DTLS.
+
+use std::{
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[cfg(feature = "tokio")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "tokio")]
+use futures_util::{
+    future::{self, Either},
+    pin_mut, Stream,
+};
+#[cfg(feature = "tokio")]
+use tokio::{
+    sync::{mpsc, Notify},
+    task::JoinHandle,
+};
+
+#[cfg(feature = "v3")]
+use std::{collections::HashMap, time::Instant};
+
+#[cfg(feature = "v3")]
+use openssl::{
+    pkey::{PKeyRef, Private},
+    ssl::{ErrorCode, SslStream},
+    x509::X509Ref,
+};
+
+#[cfg(feature = "tokio")]
+use crate::OwnedPdu;
+use crate::{pdu, Error, MessageType, Pdu, BUFFER_SIZE};
+#[cfg(feature = "v3")]
+use crate::{
+    tls::{self, CertificateMap},
+    v3::{self, AuthErrorKind},
+    Version,
+};
+
+/// How often a blocked listener checks whether it has been shut down.
+const SHUTDOWN_POLL: Duration = Duration::from_millis(100);
+
+/// Limit of the DTLS associations of a [`DtlsTrapListener`].
+#[cfg(feature = "v3")]
+const MAX_ASSOCIATIONS: usize = 1024;
+
+/// DTLS handshakes which have not completed after this time may be abandoned.
+#[cfg(feature = "v3")]
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// DTLS associations idle for this time may be closed.
+#[cfg(feature = "v3")]
+const IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Queue size of [`AsyncTrapListener`] notification streams.
+#[cfg(feature = "tokio")]
+const DEFAULT_QUEUE_SIZE: usize = 128;
+
+type ErrorHandler = Box<dyn FnMut(SocketAddr, &Error) + Send>;
+
+#[derive(Debug, Default)]
+struct Shutdown {
+    stopped: AtomicBool,
+    #[cfg(feature = "tokio")]
+    notify: Notify,
+}
+
+/// Stops a notification receiver, from another thread or task.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<Shutdown>,
+}
+
+impl ShutdownHandle {
+    /// Asks the receiver to stop. Notifications which are being handled are completed first.
+    pub fn shutdown(&self) {
+        self.inner.stopped.store(true, Ordering::SeqCst);
+        #[cfg(feature = "tokio")]
+        self.inner.notify.notify_waiters();
+    }
+
+    fn is_shutdown(&self) -> bool {
+        self.inner.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Runs the future until the receiver is shut down, returns `None` when it is.
+    #[cfg(feature = "tokio")]
+    async fn until_shutdown<F: Future>(&self, future: F) -> Option<F::Output> {
+        // registered before the check, so that no notification is missed
+        let notified = self.inner.notify.notified();
+        if self.is_shutdown() {
+            return None;
+        }
+        pin_mut!(future, notified);
+        match future::select(future, notified).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+/// Parses the received datagrams and acknowledges inform requests, shared by the synchronous and
+/// asynchronous listeners.
+struct Decoder {
+    on_error: ErrorHandler,
+    ack: pdu::Buf,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Self {
+            on_error: Box::new(|_, _| {}),
+            ack: pdu::Buf::default(),
+        }
+    }
+
+    /// Parses the notification, SNMPv3 ones with the security parameters. Reports the datagram
+    /// to the error handler when it can not be parsed or is not a notification. Inform requests
+    /// are returned with the Response which acknowledges them.
+    fn decode<'a>(
+        &'a mut self,
+        msg: &'a [u8],
+        source: SocketAddr,
+        #[cfg(feature = "v3")] security: Option<&'a mut v3::Security>,
+    ) -> Option<(Pdu<'a>, Option<&'a [u8]>)> {
+        #[cfg(feature = "v3")]
+        let parsed = Pdu::from_bytes_with_context(msg, security);
+        #[cfg(not(feature = "v3"))]
+        let parsed = Pdu::from_bytes(msg).map(|pdu| (pdu, ()));
+        #[cfg_attr(not(feature = "v3"), allow(unused_variables))]
+        let (pdu, security) = match parsed {
+            Ok((pdu, security)) if is_notification(pdu.message_type) => (pdu, security),
+            Ok(_) => {
+                (self.on_error)(source, &Error::AsnWrongType);
+                return None;
+            }
+            Err(e) => {
+                (self.on_error)(source, &e);
+                return None;
+            }
+        };
+        if pdu.message_type != MessageType::InformRequest {
+            return Some((pdu, None));
+        }
+        match pdu::build_inform_response(
+            &pdu,
+            &mut self.ack,
+            #[cfg(feature = "v3")]
+            security,
+        ) {
+            Ok(()) => Some((pdu, Some(&self.ack))),
+            Err(e) => {
+                (self.on_error)(source, &e);
+                Some((pdu, None))
+            }
+        }
+    }
+}
+
+/// Receive errors after which listening goes on.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+/// Whether agents send the message type unsolicited, as a notification.
+fn is_notification(message_type: MessageType) -> bool {
+    matches!(
+        message_type,
+        MessageType::TrapV1 | MessageType::Trap | MessageType::InformRequest
+    )
+}
+
+/// Synchronous notification receiver, listens on a UDP port (usually 162) for SNMPv1 traps,
+/// SNMPv2c traps and inform requests.
+///
+/// Inform requests are acknowledged with a Response, before they are passed to the handler.
+/// Datagrams which can not be parsed or are not notifications are dropped, after being passed to
+/// the error handler if one is set with [`with_error_handler`](Self::with_error_handler).
+///
+/// ```no_run
+/// use std::{thread, time::Duration};
+/// use snmp2::TrapListener;
+///
+/// let mut listener = TrapListener::bind("0.0.0.0:162").unwrap();
+/// // listen for a minute
+/// let shutdown = listener.shutdown_handle();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_secs(60));
+///     shutdown.shutdown();
+/// });
+/// listener
+///     .run(|source, pdu| {
+///         println!("{} sent {:?}: {:?}", source, pdu.message_type, pdu.varbinds);
+///     })
+///     .unwrap();
+/// ```
+#[allow(clippy::module_name_repetitions)]
+pub struct TrapListener {
+    socket: UdpSocket,
+    shutdown: ShutdownHandle,
+    decoder: Decoder,
+    #[cfg(feature = "v3")]
+    security: Option<v3::Security>,
+    buf: Vec<u8>,
+}
+
+impl TrapListener {
+    /// Binds the listener to the local address.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(SHUTDOWN_POLL))?;
+        Ok(Self {
+            socket,
+            shutdown: ShutdownHandle::default(),
+            decoder: Decoder::new(),
+            #[cfg(feature = "v3")]
+            security: None,
+            buf: vec![0; BUFFER_SIZE],
+        })
+    }
+
+    /// Handles the datagrams which are dropped, e.g. to log them. Without a handler, they are
+    /// dropped silently.
+    #[must_use]
+    pub fn with_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(SocketAddr, &Error) + Send + 'static,
+    {
+        self.decoder.on_error = Box::new(handler);
+        self
+    }
+
+    /// Receives SNMPv3 notifications with the security parameters. Without them, SNMPv3
+    /// notifications are dropped.
+    ///
+    /// The receiver of inform requests is their authoritative engine: the parameters hold the
+    /// local engine ID, boots and time, which the senders must be configured with, as engine
+    /// discovery requests are not answered.
+    #[cfg(feature = "v3")]
+    #[must_use]
+    pub fn with_security(mut self, security: v3::Security) -> Self {
+        self.security = Some(security);
+        self
+    }
+
+    /// Local address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Handle which stops [`run`](Self::run).
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Passes the received notifications with their source addresses to the handler, until the
+    /// listener is shut down or fails to receive.
+    pub fn run<F>(&mut self, mut handler: F) -> io::Result<()>
+    where
+        F: FnMut(SocketAddr, &Pdu),
+    {
+        while !self.shutdown.is_shutdown() {
+            let (len, source) = match self.socket.recv_from(&mut self.buf) {
+                Ok(received) => received,
+                Err(e) if is_transient(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            let Some((pdu, ack)) = self.decoder.decode(
+                &self.buf[..len],
+                source,
+                #[cfg(feature = "v3")]
+                self.security.as_mut(),
+            ) else {
+                continue;
+            };
+            let acked = ack.map(|ack| self.socket.send_to(ack, source));
+            handler(source, &pdu);
+            if let Some(Err(_)) = acked {
+                (self.decoder.on_error)(source, &Error::Send);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// DTLS association of a [`DtlsTrapListener`] with an agent.
+#[cfg(feature = "v3")]
+struct Association {
+    stream: SslStream<tls::Channel>,
+    /// Security of the agent, once the handshake has authenticated it.
+    security: Option<v3::Security>,
+    active: Instant,
+}
+
+#[cfg(feature = "v3")]
+impl Association {
+    fn is_expired(&self, now: Instant) -> bool {
+        let timeout = if self.security.is_some() {
+            IDLE_TIMEOUT
+        } else {
+            HANDSHAKE_TIMEOUT
+        };
+        now.duration_since(self.active) > timeout
+    }
+
+    /// Goes on with the handshake and passes the received notifications to the handler, returns
+    /// whether the association is still open.
+    fn receive<F>(
+        &mut self,
+        socket: &UdpSocket,
+        source: SocketAddr,
+        map: &CertificateMap,
+        decoder: &mut Decoder,
+        buf: &mut [u8],
+        handler: &mut F,
+    ) -> bool
+    where
+        F: FnMut(SocketAddr, &Pdu),
+    {
+        let security = match self.security {
+            Some(ref mut security) => security,
+            None => {
+                let name = match self.stream.accept() {
+                    Ok(()) => map.peer_security_name(self.stream.ssl()),
+                    Err(e) if e.code() == ErrorCode::WANT_READ => return true,
+                    Err(_) => None,
+                };
+                let Some(name) = name else {
+                    let error = Error::AuthFailure(AuthErrorKind::NotAuthenticated);
+                    (decoder.on_error)(source, &error);
+                    return false;
+                };
+                self.security.insert(v3::Security::tsm(&name))
+            }
+        };
+        loop {
+            let len = match self.stream.ssl_read(buf) {
+                Ok(len) => len,
+                Err(e) if e.code() == ErrorCode::WANT_READ => return true,
+                Err(e) => {
+                    if e.code() == ErrorCode::ZERO_RETURN {
+                        // closed by the agent
+                        let _ = self.stream.shutdown();
+                    }
+                    return false;
+                }
+            };
+            let Some((pdu, ack)) = decoder.decode(&buf[..len], source, Some(security)) else {
+                continue;
+            };
+            // the transport secures SNMPv3 messages only
+            if pdu.version().ok() != Some(Version::V3) {
+                (decoder.on_error)(source, &Error::UnsupportedVersion);
+                continue;
+            }
+            let acked = ack.map(|ack| {
+                self.stream.ssl_write(ack).is_ok() && flush(&mut self.stream, socket, source)
+            });
+            handler(source, &pdu);
+            if acked == Some(false) {
+                (decoder.on_error)(source, &Error::Send);
+            }
+        }
+    }
+}
+
+/// Sends the datagrams written by the association, returns whether all of them have been sent.
+#[cfg(feature = "v3")]
+fn flush(stream: &mut SslStream<tls::Channel>, socket: &UdpSocket, peer: SocketAddr) -> bool {
+    stream.get_mut().drain().fold(true, |sent, datagram| {
+        socket.send_to(&datagram, peer).is_ok() && sent
+    })
+}
+
+/// Synchronous notification receiver over DTLS (RFC 6353), listens on a UDP port (usually 10162)
+/// for SNMPv3 traps and inform requests with the Transport Security Model.
+///
+/// The agents are authenticated by their certificates, which the map must apply to, see
+/// [`CertificateMap`]: the security name it derives is the `community` of their notifications.
+/// The handshakes which fail are passed to the error handler. An association is kept only once
+/// the agent has returned the cookie of its address (RFC 6347 section 4.2.1), so spoofed
+/// addresses get a small HelloVerifyRequest and no state. Up to 1024 associations are kept, the
+/// ones idle for 10 minutes are closed when the limit is reached, and new agents are refused
+/// while it is. Otherwise like [`TrapListener`].
+///
+/// ```no_run
+/// use snmp2::{
+///     tls::{CertificateMap, Fingerprint, SecurityNameMapping},
+///     v3::AuthProtocol,
+///     DtlsTrapListener,
+/// };
+/// use openssl::{pkey::PKey, x509::X509};
+///
+/// let certificate = X509::from_pem(&std::fs::read("receiver.crt").unwrap()).unwrap();
+/// let key = PKey::private_key_from_pem(&std::fs::read("receiver.key").unwrap()).unwrap();
+/// let agent_certificate = X509::from_pem(&std::fs::read("agent.crt").unwrap()).unwrap();
+/// let map = CertificateMap::new().with_entry(
+///     Fingerprint::of(&agent_certificate, AuthProtocol::Sha256).unwrap(),
+///     SecurityNameMapping::CommonName,
+/// );
+/// let mut listener = DtlsTrapListener::bind("0.0.0.0:10162", &certificate, &key, map).unwrap();
+/// listener
+///     .run(|source, pdu| {
+///         let name = String::from_utf8_lossy(pdu.community);
+///         println!("{} ({}) sent {:?}", source, name, pdu.varbinds);
+///     })
+///     .unwrap();
+/// ```
+#[cfg(feature = "v3")]
+pub struct DtlsTrapListener {
+    socket: UdpSocket,
+    shutdown: ShutdownHandle,
+    decoder: Decoder,
+    server: tls::DtlsServer,
+    map: CertificateMap,
+    associations: HashMap<SocketAddr, Association>,
+    buf: Vec<u8>,
+    record: Vec<u8>,
+}
+
+#[cfg(feature = "v3")]
+impl DtlsTrapListener {
+    /// Binds the listener to the local address, with its certificate and private key.
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        certificate: &X509Ref,
+        key: &PKeyRef<Private>,
+        map: CertificateMap,
+    ) -> io::Result<Self> {
+        let server = tls::DtlsServer::new(certificate, key, &map)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(SHUTDOWN_POLL))?;
+        Ok(Self {
+            socket,
+            shutdown: ShutdownHandle::default(),
+            decoder: Decoder::new(),
+            server,
+            map,
+            associations: HashMap::new(),
+            buf: vec![0; BUFFER_SIZE],
+            record: vec![0; BUFFER_SIZE],
+        })
+    }
+
+    /// Handles the datagrams which are dropped and the failed handshakes, e.g. to log them.
+    /// Without a handler, they are dropped silently.
+    #[must_use]
+    pub fn with_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(SocketAddr, &Error) + Send + 'static,
+    {
+        self.decoder.on_error = Box::new(handler);
+        self
+    }
+
+    /// Local address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Handle which stops [`run`](Self::run).
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Passes the received notifications with their source addresses to the handler, until the
+    /// listener is shut down or fails to receive. The associations are then closed.
+    pub fn run<F>(&mut self, mut handler: F) -> io::Result<()>
+    where
+        F: FnMut(SocketAddr, &Pdu),
+    {
+        let result = self.listen(&mut handler);
+        for (peer, mut association) in self.associations.drain() {
+            if association.security.is_some() {
+                let _ = association.stream.shutdown();
+                if !flush(&mut association.stream, &self.socket, peer) {
+                    (self.decoder.on_error)(peer, &Error::Send);
+                }
+            }
+        }
+        result
+    }
+
+    fn listen<F>(&mut self, handler: &mut F) -> io::Result<()>
+    where
+        F: FnMut(SocketAddr, &Pdu),
+    {
+        while !self.shutdown.is_shutdown() {
+            let (len, source) = match self.socket.recv_from(&mut self.buf) {
+                Ok(received) => received,
+                Err(e) if is_transient(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            let now = Instant::now();
+            let mut association = match self.associations.remove(&source) {
+                Some(mut association) => {
+                    association.stream.get_mut().push(&self.buf[..len]);
+                    association
+                }
+                None => match self.admit(source, len, now)? {
+                    Some(association) => association,
+                    None => continue,
+                },
+            };
+            association.active = now;
+            let open = association.receive(
+                &self.socket,
+                source,
+                &self.map,
+                &mut self.decoder,
+                &mut self.record,
+                handler,
+            );
+            // handshake flights and alerts
+            if !flush(&mut association.stream, &self.socket, source) {
+                (self.decoder.on_error)(source, &Error::Send);
+            }
+            if open {
+                self.associations.insert(source, association);
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts an association with a new agent once it has returned the cookie of its address,
+    /// the ClientHello messages without it are answered statelessly.
+    fn admit(
+        &mut self,
+        source: SocketAddr,
+        len: usize,
+        now: Instant,
+    ) -> io::Result<Option<Association>> {
+        let (mut stream, verified) = self
+            .server
+            .listen(source, &self.buf[..len])
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if !verified {
+            // HelloVerifyRequest
+            if !flush(&mut stream, &self.socket, source) {
+                (self.decoder.on_error)(source, &Error::Send);
+            }
+            return Ok(None);
+        }
+        if !self.make_room(now) {
+            (self.decoder.on_error)(source, &Error::BufferOverflow);
+            return Ok(None);
+        }
+        Ok(Some(Association {
+            stream,
+            security: None,
+            active: now,
+        }))
+    }
+
+    /// Closes the expired associations if the limit is reached, returns whether a new one can be
+    /// kept.
+    fn make_room(&mut self, now: Instant) -> bool {
+        if self.associations.len() >= MAX_ASSOCIATIONS {
+            self.associations
+                .retain(|_, association| !association.is_expired(now));
+        }
+        self.associations.len() < MAX_ASSOCIATIONS
+    }
+}
+
+/// Notification received by a [`NotificationStream`].
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Address of the agent which sent the notification.
+    pub source: SocketAddr,
+    /// The notification. For SNMPv3, `community` is the user name of the sender.
+    pub pdu: OwnedPdu,
+}
+
+/// Asynchronous notification receiver, see [`TrapListener`]. The notifications are received by a
+/// background task and yielded as a [`NotificationStream`].
+///
+/// ```no_run
+/// use futures_util::StreamExt as _;
+/// use snmp2::AsyncTrapListener;
+///
+/// async fn listen() {
+///     let mut notifications = AsyncTrapListener::bind("0.0.0.0:162")
+///         .await
+///         .unwrap()
+///         .into_stream();
+///     while let Some(notification) = notifications.next().await {
+///         println!("{}: {:?}", notification.source, notification.pdu.varbinds);
+///     }
+/// }
+/// ```
+#[cfg(feature = "tokio")]
+#[allow(clippy::module_name_repetitions)]
+pub struct AsyncTrapListener {
+    socket: tokio::net::UdpSocket,
+    shutdown: ShutdownHandle,
+    decoder: Decoder,
+    #[cfg(feature = "v3")]
+    security: Option<v3::Security>,
+    queue_size: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl AsyncTrapListener {
+    /// Binds the listener to the local address.
+    pub async fn bind<A: tokio::net::ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self {
+            socket: tokio::net::UdpSocket::bind(addr).await?,
+            shutdown: ShutdownHandle::default(),
+            decoder: Decoder::new(),
+            #[cfg(feature = "v3")]
+            security: None,
+            queue_size: DEFAULT_QUEUE_SIZE,
+        })
+    }
+
+    /// Handles the datagrams which are dropped, e.g. to log them. Without a handler, they are
+    /// dropped silently.
+    #[must_use]
+    pub fn with_error_handler<F>(mut self, handler: F) -> Self
+    where
+        F: FnMut(SocketAddr, &Error) + Send + 'static,
+    {
+        self.decoder.on_error = Box::new(handler);
+        self
+    }
+
+    /// Receives SNMPv3 notifications with the security parameters. Without them, SNMPv3
+    /// notifications are dropped.
+    ///
+    /// The receiver of inform requests is their authoritative engine: the parameters hold the
+    /// local engine ID, boots and time, which the senders must be configured with, as engine
+    /// discovery requests are not answered.
+    #[cfg(feature = "v3")]
+    #[must_use]
+    pub fn with_security(mut self, security: v3::Security) -> Self {
+        self.security = Some(security);
+        self
+    }
+
+    /// Number of notifications queued until the stream is polled (128 by default). While the
+    /// queue is full, no more datagrams are read from the socket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size is zero.
+    #[must_use]
+    pub fn with_queue_size(mut self, size: usize) -> Self {
+        assert!(size > 0, "queue size must be positive");
+        self.queue_size = size;
+        self
+    }
+
+    /// Local address the listener is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Starts receiving notifications. Must be called within a tokio runtime, as the listener
+    /// spawns a background receive task.
+    pub fn into_stream(self) -> NotificationStream {
+        let (tx, rx) = mpsc::channel(self.queue_size);
+        let shutdown = self.shutdown.clone();
+        let receiver = tokio::spawn(self.receive(tx));
+        NotificationStream {
+            rx,
+            shutdown,
+            receiver,
+        }
+    }
+
+    async fn receive(mut self, tx: mpsc::Sender<Notification>) {
+        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            let Some(received) = self.shutdown.until_shutdown(self.socket.recv_from(&mut buf)).await
+            else {
+                return;
+            };
+            let (len, source) = match received {
+                Ok(received) => received,
+                Err(e) if is_transient(&e) => continue,
+                Err(_) => return,
+            };
+            let Some((pdu, ack)) = self.decoder.decode(
+                &buf[..len],
+                source,
+                #[cfg(feature = "v3")]
+                self.security.as_mut(),
+            ) else {
+                continue;
+            };
+            let acked = match ack {
+                Some(ack) => Some(self.socket.send_to(ack, source).await),
+                None => None,
+            };
+            let notification = Notification {
+                source,
+                pdu: pdu.to_owned_pdu(),
+            };
+            if let Some(Err(_)) = acked {
+                (self.decoder.on_error)(source, &Error::Send);
+            }
+            match self.shutdown.until_shutdown(tx.send(notification)).await {
+                Some(Ok(())) => {}
+                // shut down or the stream has been dropped
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Stream of the notifications received by an [`AsyncTrapListener`].
+///
+/// After shutdown, the notifications already queued are yielded before the stream ends. The stream
+/// ends as well when the socket fails to receive. Dropping the stream stops the listener.
+#[cfg(feature = "tokio")]
+pub struct NotificationStream {
+    rx: mpsc::Receiver<Notification>,
+    shutdown: ShutdownHandle,
+    receiver: JoinHandle<()>,
+}
+
+#[cfg(feature = "tokio")]
+impl NotificationStream {
+    /// Handle which stops receiving notifications.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Stream for NotificationStream {
+    type Item = Notification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Drop for NotificationStream {
+    fn drop(&mut self) {
+        self.receiver.abort();
+    }
+}
@@ -15,6 +15,10 @@ use crate::{
 
 const ENGINE_TIME_WINDOW: i64 = 150;
 
+/// The `localEngineID` context engine ID (RFC 5343), used with the Transport Security Model when
+/// the engine ID of the agent is not known.
+pub const LOCAL_ENGINE_ID: &[u8] = &[0x80, 0x00, 0x00, 0x00, 0x06];
+
 #[cfg(feature = "v3")]
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum AuthErrorKind {
@@ -92,6 +96,16 @@ impl AuthoritativeState {
         self.engine_time = engine_time;
         self.start_time = Instant::now();
     }
+    /// Engine time of the authoritative engine, advanced by the time passed since it has been
+    /// received.
+    fn current_engine_time(&self) -> i64 {
+        if self.engine_boots == 0 {
+            return self.engine_time;
+        }
+        i64::try_from(self.start_time.elapsed().as_secs())
+            .unwrap_or(i64::MAX)
+            .saturating_add(self.engine_time)
+    }
     fn correct_engine_time(&mut self) {
         if self.engine_boots == 0 {
             self.engine_time_current = 0;
@@ -156,8 +170,30 @@ impl AuthoritativeState {
     }
 }
 
+/// Security model of SNMPv3 messages (msgSecurityModel).
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum SecurityModel {
+    /// User-based Security Model (RFC 3414): messages are authenticated and encrypted with keys
+    /// derived from the user passwords.
+    #[default]
+    Usm,
+    /// Transport Security Model (RFC 5591): messages are secured by the transport, e.g.
+    /// [TLS](crate::tls), and the security name is derived from the transport identity.
+    Tsm,
+}
+
+impl SecurityModel {
+    fn number(self) -> i64 {
+        match self {
+            SecurityModel::Usm => 3,
+            SecurityModel::Tsm => 4,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Security {
+    pub(crate) model: SecurityModel,
     pub(crate) username: Vec<u8>,
     pub(crate) authentication_password: Vec<u8>,
     pub(crate) auth: Auth,
@@ -169,6 +205,7 @@ pub struct Security {
 impl Security {
     pub fn new(username: &[u8], authentication_password: &[u8]) -> Self {
         Self {
+            model: SecurityModel::Usm,
             username: username.to_vec(),
             authentication_password: authentication_password.to_vec(),
             auth: Auth::AuthNoPriv,
@@ -177,6 +214,16 @@ impl Security {
             plain_buf: Vec::new(),
         }
     }
+    /// Security of the Transport Security Model, for sessions over a secure transport such as
+    /// [TLS](crate::tls). The security name is the one the transport identity (e.g. the client
+    /// certificate) maps to on the agent. No engine discovery is needed, the context engine ID is
+    /// [`LOCAL_ENGINE_ID`] unless set with [`Security::with_engine_id`].
+    pub fn tsm(security_name: &[u8]) -> Self {
+        let mut security = Self::new(security_name, &[]);
+        security.model = SecurityModel::Tsm;
+        security.authoritative_state.engine_id = LOCAL_ENGINE_ID.to_vec();
+        security
+    }
     pub fn with_auth(mut self, auth: Auth) -> Self {
         self.auth = auth;
         self
@@ -220,6 +267,9 @@ impl Security {
         Ok(buf)
     }
     pub(crate) fn update_key(&mut self) -> Result<()> {
+        if self.model == SecurityModel::Tsm {
+            return Ok(());
+        }
         self.authoritative_state
             .update_auth_key(&self.authentication_password, self.auth_protocol)?;
         if let Auth::AuthPriv {
@@ -243,6 +293,14 @@ impl Security {
     pub fn username(&self) -> &[u8] {
         &self.username
     }
+    pub fn security_model(&self) -> SecurityModel {
+        self.model
+    }
+    /// Whether the engine must be discovered with [`init`](crate::SyncSession::init), which is
+    /// the case for USM only.
+    pub(crate) fn is_usm(&self) -> bool {
+        self.model == SecurityModel::Usm
+    }
     /// corrects authoritative state engine time using local monotonic time
     pub(crate) fn correct_authoritative_engine_time(&mut self) {
         self.authoritative_state.correct_engine_time();
@@ -433,7 +491,7 @@ pub enum Auth {
     },
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum AuthProtocol {
     Md5,
     Sha1,
@@ -447,7 +505,7 @@ impl AuthProtocol {
     fn create_hasher(self) -> Result<Hasher> {
         Hasher::new(self.digest()).map_err(Into::into)
     }
-    fn digest(self) -> MessageDigest {
+    pub(crate) fn digest(self) -> MessageDigest {
         match self {
             AuthProtocol::Md5 => MessageDigest::md5(),
             AuthProtocol::Sha1 => MessageDigest::sha1(),
@@ -468,12 +526,75 @@ pub enum Cipher {
 }
 
 impl<'a> Pdu<'a> {
-    #[allow(clippy::too_many_lines)]
+    /// Parses an SNMPv3 message. The security context is updated only if the message passes all
+    /// the checks, or if it is a discovery response (the function returns [`Error::AuthUpdated`]).
+    /// The context is returned for reading along with the PDU, which may borrow it.
     pub(crate) fn parse_v3(
         bytes: &'a [u8],
-        mut rdr: AsnReader<'a>,
+        rdr: AsnReader<'a>,
         security: &'a mut Security,
-    ) -> Result<Pdu<'a>> {
+    ) -> Result<(Pdu<'a>, &'a Security)> {
+        let (header, security) = Self::check_v3(bytes, rdr, security)?;
+        let scoped_pdu = header.scoped_pdu;
+        let pdu = Pdu {
+            version: Version::V3 as i64,
+            community: header.username.unwrap_or(&security.username),
+            message_type: scoped_pdu.message_type,
+            req_id: scoped_pdu.req_id,
+            error_status: scoped_pdu.error_status,
+            error_index: scoped_pdu.error_index,
+            varbinds: Varbinds::from_bytes(scoped_pdu.varbind_bytes),
+            v1_trap_info: None,
+            v3_msg_id: header.msg_id,
+        };
+        Ok((pdu, security))
+    }
+
+    /// Checks the message and parses its scoped PDU, which may borrow the decrypted data of the
+    /// security context. The context is restored if a check fails.
+    fn check_v3(
+        bytes: &'a [u8],
+        rdr: AsnReader<'a>,
+        security: &'a mut Security,
+    ) -> Result<(V3Header<'a>, &'a Security)> {
+        let saved_state = security.authoritative_state.clone();
+        let result = Self::check_security(bytes, rdr, security).and_then(|message| {
+            let scoped_pdu = if let Some(scoped_pdu_seq) = message.scoped_pdu_seq {
+                ScopedPdu::parse(scoped_pdu_seq)?
+            } else {
+                ScopedPdu::parse(
+                    AsnReader::from_bytes(&security.plain_buf).read_raw(asn1::TYPE_SEQUENCE)?,
+                )?
+            };
+            message.check_time(
+                scoped_pdu.message_type,
+                security.authoritative_state.engine_boots,
+            )?;
+            Ok(V3Header {
+                msg_id: message.msg_id,
+                username: message.username,
+                scoped_pdu,
+            })
+        });
+        match result {
+            Ok(header) => Ok((header, security)),
+            Err(e) => {
+                if e != Error::AuthUpdated {
+                    security.authoritative_state = saved_state;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Checks the header and the security parameters of the message, and decrypts its scoped
+    /// PDU if needed.
+    #[allow(clippy::too_many_lines)]
+    fn check_security(
+        bytes: &'a [u8],
+        mut rdr: AsnReader<'a>,
+        security: &mut Security,
+    ) -> Result<CheckedMessage<'a>> {
         let global_data_seq = rdr.read_raw(asn1::TYPE_SEQUENCE)?;
         let mut global_data_rdr = AsnReader::from_bytes(global_data_seq);
         let msg_id = global_data_rdr.read_asn_integer()?;
@@ -487,9 +608,21 @@ impl<'a> Pdu<'a> {
             .copied()
             .unwrap_or_default();
         let security_model = global_data_rdr.read_asn_integer()?;
-        if security_model != 3 {
+        if security_model != security.model.number() {
             return Err(Error::AuthFailure(AuthErrorKind::UnsupportedUSM));
         }
+        if security.model == SecurityModel::Tsm {
+            // RFC 5591: the transport has authenticated the peer and protects the message, the
+            // security parameters are empty and the scoped PDU is never encrypted
+            rdr.read_asn_octetstring()?;
+            return Ok(CheckedMessage {
+                msg_id: i32::try_from(msg_id).map_err(|_| Error::ValueOutOfRange)?,
+                username: None,
+                scoped_pdu_seq: Some(rdr.read_raw(asn1::TYPE_SEQUENCE)?),
+                timeliness: None,
+                is_discovery: false,
+            });
+        }
         let security_params = rdr.read_asn_octetstring()?;
         let security_seq = AsnReader::from_bytes(security_params).read_raw(asn1::TYPE_SEQUENCE)?;
         let mut security_rdr = AsnReader::from_bytes(security_seq);
@@ -512,7 +645,9 @@ impl<'a> Pdu<'a> {
         // See RFC3414 section 4 and section 3.2.7.a
         let mut is_discovery = false;
 
-        let mut prev_engine_time = security.engine_time();
+        // the engine time has advanced since the previous message, e.g. informs may be minutes
+        // apart
+        let mut prev_engine_time = security.authoritative_state.current_engine_time();
 
         if flags & V3_MSG_FLAGS_AUTH == 0 {
             if security.authoritative_state.engine_id.is_empty() {
@@ -569,36 +704,96 @@ impl<'a> Pdu<'a> {
             if security.need_encrypt() && !is_discovery {
                 return Err(Error::AuthFailure(AuthErrorKind::ReplyNotEncrypted));
             }
-            rdr.read_raw(asn1::TYPE_SEQUENCE)?
+            Some(rdr.read_raw(asn1::TYPE_SEQUENCE)?)
         } else {
             let encrypted_pdu = rdr.read_asn_octetstring()?;
             security.decrypt(encrypted_pdu, priv_params)?;
-            let mut rdr = AsnReader::from_bytes(&security.plain_buf);
-            rdr.read_raw(asn1::TYPE_SEQUENCE)?
+            None
         };
 
-        let mut scoped_pdu_rdr = AsnReader::from_bytes(scoped_pdu_seq);
-
-        let _context_engine_id = scoped_pdu_rdr.read_asn_octetstring()?;
-
-        let _context_name = scoped_pdu_rdr.read_asn_octetstring()?;
+        Ok(CheckedMessage {
+            msg_id: i32::try_from(msg_id).map_err(|_| Error::ValueOutOfRange)?,
+            username: Some(username),
+            scoped_pdu_seq,
+            timeliness: Some(Timeliness {
+                engine_boots,
+                engine_time,
+                prev_engine_time,
+            }),
+            is_discovery,
+        })
+    }
+}
 
-        let ident = scoped_pdu_rdr.peek_byte()?;
+/// SNMPv3 message which has passed the security checks. The scoped PDU is `None` if it has been
+/// decrypted into the security context buffer.
+struct CheckedMessage<'a> {
+    msg_id: i32,
+    username: Option<&'a [u8]>,
+    scoped_pdu_seq: Option<&'a [u8]>,
+    /// `None` for TSM messages, the transport protects them against replays.
+    timeliness: Option<Timeliness>,
+    is_discovery: bool,
+}
 
-        let message_type = MessageType::from_ident(ident)?;
+/// Engine boots and time of a message, and the engine time before the message was received.
+struct Timeliness {
+    engine_boots: i64,
+    engine_time: i64,
+    prev_engine_time: i64,
+}
 
+impl CheckedMessage<'_> {
+    /// Checks that the message is in the time window (RFC 3414 section 3.2.7), except for traps
+    /// which are sent by their own authoritative engine.
+    fn check_time(&self, message_type: MessageType, local_engine_boots: i64) -> Result<()> {
         if message_type == MessageType::Trap {
-            is_discovery = false;
-        } else {
-            if security.engine_boots() > engine_boots {
+            return Ok(());
+        }
+        if let Some(timeliness) = &self.timeliness {
+            if local_engine_boots > timeliness.engine_boots {
                 return Err(Error::AuthFailure(AuthErrorKind::EngineBootsMismatch));
             }
-            if security.engine_boots() == engine_boots
-                && (engine_time - prev_engine_time).abs() > ENGINE_TIME_WINDOW
+            if local_engine_boots == timeliness.engine_boots
+                && (timeliness.engine_time - timeliness.prev_engine_time).abs() > ENGINE_TIME_WINDOW
             {
                 return Err(Error::AuthFailure(AuthErrorKind::EngineTimeMismatch));
             }
         }
+        if self.is_discovery {
+            return Err(Error::AuthUpdated);
+        }
+        Ok(())
+    }
+}
+
+/// Checked SNMPv3 message header and its scoped PDU. The user name is `None` for TSM messages,
+/// which are sent by the security name of the context.
+struct V3Header<'a> {
+    msg_id: i32,
+    username: Option<&'a [u8]>,
+    scoped_pdu: ScopedPdu<'a>,
+}
+
+struct ScopedPdu<'a> {
+    message_type: MessageType,
+    req_id: i32,
+    error_status: u32,
+    error_index: u32,
+    varbind_bytes: &'a [u8],
+}
+
+impl<'a> ScopedPdu<'a> {
+    fn parse(scoped_pdu_seq: &'a [u8]) -> Result<Self> {
+        let mut scoped_pdu_rdr = AsnReader::from_bytes(scoped_pdu_seq);
+
+        let _context_engine_id = scoped_pdu_rdr.read_asn_octetstring()?;
+
+        let _context_name = scoped_pdu_rdr.read_asn_octetstring()?;
+
+        let ident = scoped_pdu_rdr.peek_byte()?;
+
+        let message_type = MessageType::from_ident(ident)?;
 
         let mut response_pdu = AsnReader::from_bytes(scoped_pdu_rdr.read_raw(ident)?);
 
@@ -610,22 +805,13 @@ impl<'a> Pdu<'a> {
         let error_index: u32 = u32::try_from(response_pdu.read_asn_integer()?)?;
 
         let varbind_bytes = response_pdu.read_raw(asn1::TYPE_SEQUENCE)?;
-        let varbinds = Varbinds::from_bytes(varbind_bytes);
-
-        if is_discovery {
-            return Err(Error::AuthUpdated);
-        }
 
-        Ok(Pdu {
-            version: Version::V3 as i64,
-            community: username,
+        Ok(Self {
             message_type,
             req_id,
             error_status,
             error_index,
-            varbinds,
-            v1_trap_info: None,
-            v3_msg_id: i32::try_from(msg_id).map_err(|_| Error::ValueOutOfRange)?,
+            varbind_bytes,
         })
     }
 }
@@ -662,8 +848,12 @@ pub(crate) fn build_init(req_id: i32, buf: &mut Buf) {
     });
 }
 
+/// Builds an SNMPv3 message. The msgID of requests is their request ID, responses repeat the
+/// msgID of the request.
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn build(
     ident: u8,
+    msg_id: i32,
     req_id: i32,
     values: &[(&Oid, Value)],
     non_repeaters: u32,
@@ -672,6 +862,19 @@ pub(crate) fn build(
     security: Option<&Security>,
 ) -> Result<()> {
     let security = security.ok_or(Error::AuthFailure(AuthErrorKind::SecurityNotProvided))?;
+    if security.model == SecurityModel::Tsm {
+        build_tsm(
+            ident,
+            msg_id,
+            req_id,
+            values,
+            non_repeaters,
+            max_repetitions,
+            buf,
+            security,
+        );
+        return Ok(());
+    }
     buf.reset();
     let mut sec_buf_seq = Buf::default();
     sec_buf_seq.reset();
@@ -679,7 +882,7 @@ pub(crate) fn build(
     let mut sec_buf_len = 0;
     let mut priv_params: Vec<u8> = Vec::new();
     let mut inner_len = 0;
-    let mut flags = V3_MSG_FLAGS_REPORTABLE | V3_MSG_FLAGS_AUTH;
+    let mut flags = reportable(ident) | V3_MSG_FLAGS_AUTH;
     let encrypted = if security.need_encrypt() {
         flags |= V3_MSG_FLAGS_PRIVACY;
         let mut pdu_buf = Buf::default();
@@ -722,7 +925,7 @@ pub(crate) fn build(
             buf.push_integer(3); // security_model
             buf.push_octet_string(&[flags]); // flags
             buf.push_integer(BUFFER_SIZE.try_into().unwrap()); // max_size
-            buf.push_integer(req_id.into()); // msg_id
+            buf.push_integer(msg_id.into()); // msg_id
         });
         buf.push_integer(3); // version
         auth_pos = buf.len() - l0 - (sec_buf_len - auth_pos);
@@ -736,3 +939,42 @@ pub(crate) fn build(
     buf[auth_pos..auth_pos + 12].copy_from_slice(&hmac[..12]);
     Ok(())
 }
+
+/// Reportable flag of the PDU type: only confirmed-class PDUs may be answered with a Report
+/// (RFC 3412 section 6.4).
+fn reportable(ident: u8) -> u8 {
+    match ident {
+        snmp::MSG_RESPONSE | snmp::MSG_TRAP | snmp::MSG_REPORT => 0,
+        _ => V3_MSG_FLAGS_REPORTABLE,
+    }
+}
+
+/// Builds a TSM message (RFC 5591), which has no security parameters and a plain scoped PDU.
+#[allow(clippy::too_many_arguments)]
+fn build_tsm(
+    ident: u8,
+    msg_id: i32,
+    req_id: i32,
+    values: &[(&Oid, Value)],
+    non_repeaters: u32,
+    max_repetitions: u32,
+    buf: &mut Buf,
+    security: &Security,
+) {
+    buf.reset();
+    buf.push_sequence(|buf| {
+        buf.push_sequence(|buf| {
+            pdu::build_inner(req_id, ident, values, non_repeaters, max_repetitions, buf);
+            buf.push_octet_string(&[]);
+            buf.push_octet_string(security.engine_id());
+        });
+        buf.push_octet_string(&[]); // security params
+        buf.push_sequence(|buf| {
+            buf.push_integer(SecurityModel::Tsm.number()); // security_model
+            buf.push_octet_string(&[reportable(ident) | V3_MSG_FLAGS_AUTH | V3_MSG_FLAGS_PRIVACY]); // flags
+            buf.push_integer(BUFFER_SIZE.try_into().unwrap()); // max_size
+            buf.push_integer(msg_id.into()); // msg_id
+        });
+        buf.push_integer(Version::V3 as i64);
+    });
+}
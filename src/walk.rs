@@ -0,0 +1,242 @@
+use std::{cmp::Ordering, collections::VecDeque};
+
+#[cfg(feature = "tokio")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "tokio")]
+use futures_util::{stream, Stream};
+
+use crate::{
+    snmp,
+    transport::{NetTransport, Transport},
+    Error, MessageType, Oid, OwnedValue, Pdu, Result, SyncSession, Value, Version,
+};
+#[cfg(feature = "tokio")]
+use crate::{transport::AsyncTransport, AsyncSession};
+
+/// Max-repetitions used for GETBULK requests while walking.
+pub(crate) const DEFAULT_MAX_REPETITIONS: u32 = 10;
+
+/// Compares two OIDs in the lexicographical order of their sub-identifiers, which is the order
+/// agents return them in. The encoded bytes can not be compared directly, as sub-identifiers have
+/// variable length.
+pub(crate) fn oid_cmp(a: &Oid, b: &Oid) -> Ordering {
+    match (a.iter(), b.iter()) {
+        (Some(a), Some(b)) => a.cmp(b),
+        _ => a.as_bytes().cmp(b.as_bytes()),
+    }
+}
+
+/// Walk progress, shared by the sync and async walkers.
+pub(crate) struct WalkState {
+    root: Oid<'static>,
+    last: Oid<'static>,
+    buffered: VecDeque<(Oid<'static>, OwnedValue)>,
+    error: Option<Error>,
+    done: bool,
+}
+
+impl WalkState {
+    pub(crate) fn new(root: &Oid) -> Self {
+        Self {
+            root: root.to_owned(),
+            last: root.to_owned(),
+            buffered: VecDeque::new(),
+            error: None,
+            done: false,
+        }
+    }
+
+    /// Returns the next buffered varbind. Varbinds received before an error are returned first,
+    /// then the error.
+    pub(crate) fn pop(&mut self) -> Option<Result<(Oid<'static>, OwnedValue)>> {
+        if let Some(varbind) = self.buffered.pop_front() {
+            Some(Ok(varbind))
+        } else {
+            self.error.take().map(Err)
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub(crate) fn fail(&mut self, error: Error) {
+        self.error = Some(error);
+        self.done = true;
+    }
+
+    /// OID to continue the walk from.
+    pub(crate) fn last(&self) -> &Oid<'static> {
+        &self.last
+    }
+
+    /// Processes a GETNEXT/GETBULK response. Stops the walk when the response leaves the subtree,
+    /// reaches the end of the MIB view or the agent returns OIDs out of order.
+    pub(crate) fn feed(&mut self, pdu: &Pdu) -> Result<()> {
+        if pdu.message_type != MessageType::Response {
+            return Err(Error::AsnWrongType);
+        }
+        if pdu.error_status == snmp::ERRSTATUS_NOSUCHNAME {
+            // SNMPv1 agents report the end of the MIB view this way
+            self.done = true;
+            return Ok(());
+        }
+        if pdu.error_status != snmp::ERRSTATUS_NOERROR {
+            return Err(Error::ErrorStatus {
+                status: pdu.error_status,
+                index: pdu.error_index,
+            });
+        }
+        let mut received = false;
+        for varbind in pdu.varbinds.try_iter() {
+            let (name, value) = varbind?;
+            received = true;
+            if matches!(value, Value::EndOfMibView) || !name.starts_with(&self.root) {
+                self.done = true;
+                return Ok(());
+            }
+            if oid_cmp(&name, &self.last) != Ordering::Greater {
+                return Err(Error::OidNotIncreasing);
+            }
+            self.last = name.to_owned();
+            self.buffered
+                .push_back((self.last.clone(), value.to_owned_value()));
+        }
+        if !received {
+            self.done = true;
+        }
+        Ok(())
+    }
+}
+
+/// Subtree walk iterator, see [`SyncSession::walk`].
+///
+/// The iterator ends after the first error.
+pub struct Walk<'a, T = NetTransport> {
+    session: &'a mut SyncSession<T>,
+    state: WalkState,
+    max_repetitions: u32,
+}
+
+impl<'a, T: Transport> Walk<'a, T> {
+    pub(crate) fn new(session: &'a mut SyncSession<T>, root: &Oid, max_repetitions: u32) -> Self {
+        Self {
+            session,
+            state: WalkState::new(root),
+            max_repetitions,
+        }
+    }
+
+    fn request(&mut self) -> Result<()> {
+        let last = self.state.last().clone();
+        let pdu = if self.session.version() == Version::V1 {
+            self.session.getnext(&last)?
+        } else {
+            self.session.getbulk(&[&last], 0, self.max_repetitions)?
+        };
+        self.state.feed(&pdu)
+    }
+}
+
+impl<T: Transport> Iterator for Walk<'_, T> {
+    type Item = Result<(Oid<'static>, OwnedValue)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(res) = self.state.pop() {
+                return Some(res);
+            }
+            if self.state.is_done() {
+                return None;
+            }
+            let res = self.request();
+            // the security context has been updated, the request is safe to repeat
+            #[cfg(feature = "v3")]
+            let res = if res == Err(Error::AuthUpdated) {
+                self.request()
+            } else {
+                res
+            };
+            if let Err(e) = res {
+                self.state.fail(e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+type WalkItem = Result<(Oid<'static>, OwnedValue)>;
+
+/// Subtree walk stream, see [`AsyncSession::walk`].
+///
+/// Requests are sent only when the stream is polled, dropping it stops the walk. The stream ends
+/// after the first error.
+#[cfg(feature = "tokio")]
+#[allow(clippy::module_name_repetitions)]
+pub struct AsyncWalk<'a> {
+    inner: Pin<Box<dyn Stream<Item = WalkItem> + Send + 'a>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<'a> AsyncWalk<'a> {
+    pub(crate) fn new<T: AsyncTransport + 'a>(
+        session: &'a mut AsyncSession<T>,
+        root: &Oid,
+        max_repetitions: u32,
+    ) -> Self {
+        let inner = stream::unfold(
+            (session, WalkState::new(root)),
+            move |(session, mut state)| async move {
+                loop {
+                    if let Some(res) = state.pop() {
+                        return Some((res, (session, state)));
+                    }
+                    if state.is_done() {
+                        return None;
+                    }
+                    let res = Self::request(session, &mut state, max_repetitions).await;
+                    // the security context has been updated, the request is safe to repeat
+                    #[cfg(feature = "v3")]
+                    let res = if res == Err(Error::AuthUpdated) {
+                        Self::request(session, &mut state, max_repetitions).await
+                    } else {
+                        res
+                    };
+                    if let Err(e) = res {
+                        state.fail(e);
+                    }
+                }
+            },
+        );
+        Self {
+            inner: Box::pin(inner),
+        }
+    }
+
+    async fn request<T: AsyncTransport>(
+        session: &mut AsyncSession<T>,
+        state: &mut WalkState,
+        max_repetitions: u32,
+    ) -> Result<()> {
+        let last = state.last().clone();
+        let pdu = if session.version() == Version::V1 {
+            session.getnext(&last).await?
+        } else {
+            session.getbulk(&[&last], 0, max_repetitions).await?
+        };
+        state.feed(&pdu)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Stream for AsyncWalk<'_> {
+    type Item = WalkItem;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}